@@ -0,0 +1,62 @@
+//! Checking a checkout's `Cargo.lock` against the RustSec advisory database.
+
+use anyhow::{Context, Result};
+use git2::{build::CheckoutBuilder, Repository};
+use rustsec::{Database, Lockfile};
+use std::path::Path;
+
+const ADVISORY_DB_URL: &str = "https://github.com/rustsec/advisory-db";
+
+/// An advisory that applies to a package pinned in a crate's lockfile.
+pub struct Finding {
+    pub id: String,
+    pub package: String,
+    pub version: String,
+}
+
+/// Clone the advisory database into `dir`, or bring an existing clone up to date, and
+/// load it.
+pub fn open_database(dir: &Path) -> Result<Database> {
+    if dir.join(".git").exists() {
+        let repo = Repository::open(dir).context("could not open advisory database")?;
+        repo.find_remote("origin")?
+            .fetch(&["HEAD"], None, None)
+            .context("could not update advisory database")?;
+        let head = repo.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+        repo.reset(
+            head.as_object(),
+            git2::ResetType::Hard,
+            Some(CheckoutBuilder::new().force()),
+        )?;
+    } else {
+        Repository::clone(ADVISORY_DB_URL, dir).context("could not clone advisory database")?;
+    }
+    Database::open(dir).context("could not load advisory database")
+}
+
+/// Advisories affecting the lockfile that governs `manifest`, looking in its directory
+/// and then upwards (for workspaces) as far as `root`. `None` if there is no lockfile.
+pub fn scan(db: &Database, root: &Path, manifest: &Path) -> Result<Option<Vec<Finding>>> {
+    let lockfile = manifest
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root))
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.is_file());
+    let Some(lockfile) = lockfile else {
+        return Ok(None);
+    };
+
+    let lockfile = Lockfile::load(&lockfile)
+        .with_context(|| format!("could not parse {}", lockfile.display()))?;
+    let findings = db
+        .vulnerabilities(&lockfile)
+        .into_iter()
+        .map(|vuln| Finding {
+            id: vuln.advisory.id.to_string(),
+            package: vuln.package.name.to_string(),
+            version: vuln.package.version.to_string(),
+        })
+        .collect();
+    Ok(Some(findings))
+}
@@ -0,0 +1,226 @@
+//! Grouping many crates' writes into one transaction, for `--checkpoint-every`.
+//!
+//! While a batch is open, the per-crate helpers can't start transactions of their own,
+//! so they use [`Savepoint`]s, which nest inside a batch and behave like a plain
+//! transaction outside one.
+//!
+//! Another process may have the database open (`--only-status`, a DB browser), so
+//! transactions take the write lock up front with `BEGIN IMMEDIATE`, where the busy
+//! timeout rusqlite sets on every connection (5 seconds) applies, and starting or
+//! committing one is retried if the lock stays busy for longer than that.
+
+use rusqlite::{Connection, ErrorCode};
+use std::ops::Deref;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// Extra attempts [`retry_busy`] makes after the busy timeout runs out.
+const BUSY_RETRIES: u32 = 3;
+
+/// Run `f`, trying again a few times while it fails because the database is locked.
+pub fn retry_busy<T>(mut f: impl FnMut() -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(e)
+                if attempt < BUSY_RETRIES
+                    && matches!(
+                        e.sqlite_error_code(),
+                        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+                    ) =>
+            {
+                attempt += 1;
+                warn!(
+                    "database is locked; retrying ({}/{})",
+                    attempt, BUSY_RETRIES
+                );
+                thread::sleep(Duration::from_secs(1));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run a write for crate `name` that the run can carry on without, retrying it like
+/// [`retry_busy`] and logging it if it still fails.
+pub fn try_write<T>(name: &str, f: impl FnMut() -> rusqlite::Result<T>) -> Option<T> {
+    retry_busy(f)
+        .inspect_err(|e| warn!("failed to update {} in the database: {}", name, e))
+        .ok()
+}
+
+/// An open transaction spanning several crates. Dropping it commits, so an error that
+/// ends the run early still keeps what was written.
+pub struct Batch<'c> {
+    conn: &'c Connection,
+}
+
+impl<'c> Batch<'c> {
+    pub fn begin(conn: &'c Connection) -> rusqlite::Result<Self> {
+        retry_busy(|| conn.execute_batch("BEGIN IMMEDIATE"))?;
+        Ok(Batch { conn })
+    }
+
+    /// Commit everything so far and carry on in a new transaction, running `between`
+    /// with no transaction open.
+    pub fn commit_and_continue(
+        &self,
+        between: impl FnOnce(&Connection) -> rusqlite::Result<()>,
+    ) -> rusqlite::Result<()> {
+        retry_busy(|| self.conn.execute_batch("COMMIT"))?;
+        between(self.conn)?;
+        retry_busy(|| self.conn.execute_batch("BEGIN IMMEDIATE"))
+    }
+
+    pub fn commit(self) -> rusqlite::Result<()> {
+        retry_busy(|| self.conn.execute_batch("COMMIT"))
+    }
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        if !self.conn.is_autocommit() {
+            retry_busy(|| self.conn.execute_batch("COMMIT")).ok();
+        }
+    }
+}
+
+/// A group of writes that happen all together or not at all. Rolls back on drop unless
+/// committed.
+pub struct Savepoint<'c> {
+    conn: &'c Connection,
+    /// Started its own transaction, rather than nesting in a [`Batch`].
+    outermost: bool,
+    done: bool,
+}
+
+impl<'c> Savepoint<'c> {
+    pub fn new(conn: &'c Connection) -> rusqlite::Result<Self> {
+        let outermost = conn.is_autocommit();
+        if outermost {
+            retry_busy(|| conn.execute_batch("BEGIN IMMEDIATE"))?;
+        } else {
+            conn.execute_batch("SAVEPOINT bugbot")?;
+        }
+        Ok(Savepoint {
+            conn,
+            outermost,
+            done: false,
+        })
+    }
+
+    pub fn commit(mut self) -> rusqlite::Result<()> {
+        self.done = true;
+        if self.outermost {
+            retry_busy(|| self.conn.execute_batch("COMMIT"))
+        } else {
+            self.conn.execute_batch("RELEASE bugbot")
+        }
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let rollback = if self.outermost {
+            "ROLLBACK"
+        } else {
+            "ROLLBACK TO bugbot; RELEASE bugbot"
+        };
+        self.conn.execute_batch(rollback).ok();
+    }
+}
+
+impl Deref for Savepoint<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::path::Path;
+    use std::sync::mpsc;
+
+    /// Open `path` the way a second process would, without waiting on busy locks so
+    /// that only [`retry_busy`] does any waiting.
+    fn open(path: &Path) -> Connection {
+        let conn = Connection::open(path).unwrap();
+        conn.busy_timeout(Duration::ZERO).unwrap();
+        conn
+    }
+
+    /// Hold the write lock on `path` from another connection until `release` fires.
+    fn hold_lock(path: &Path, release: mpsc::Receiver<()>) -> thread::JoinHandle<()> {
+        let conn = open(path);
+        conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+        thread::spawn(move || {
+            release.recv().ok();
+            conn.execute_batch("COMMIT").unwrap();
+        })
+    }
+
+    #[test]
+    fn waits_for_another_connection_to_let_go() {
+        let dir = TempDir::new("batch");
+        let path = dir.path().join("bugbot.sqlite");
+        let conn = open(&path);
+        conn.execute_batch("CREATE TABLE crates (name TEXT)")
+            .unwrap();
+
+        let (release, released) = mpsc::channel();
+        let holder = hold_lock(&path, released);
+        let busy = conn.execute("INSERT INTO crates VALUES ('a')", []);
+        assert_eq!(
+            busy.unwrap_err().sqlite_error_code(),
+            Some(ErrorCode::DatabaseBusy)
+        );
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(1500));
+            release.send(()).unwrap();
+        });
+        let batch = Batch::begin(&conn).unwrap();
+        conn.execute("INSERT INTO crates VALUES ('b')", []).unwrap();
+        batch.commit().unwrap();
+        releaser.join().unwrap();
+        holder.join().unwrap();
+
+        let names: Vec<String> = conn
+            .prepare("SELECT name FROM crates")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(names, ["b"]);
+    }
+
+    #[test]
+    fn gives_up_on_a_lock_that_is_never_released() {
+        let dir = TempDir::new("batch");
+        let path = dir.path().join("bugbot.sqlite");
+        let conn = open(&path);
+        conn.execute_batch("CREATE TABLE crates (name TEXT)")
+            .unwrap();
+
+        let (release, released) = mpsc::channel();
+        let holder = hold_lock(&path, released);
+        let written = try_write("a", || conn.execute("INSERT INTO crates VALUES ('a')", []));
+        assert_eq!(written, None);
+        release.send(()).unwrap();
+        holder.join().unwrap();
+
+        assert_eq!(
+            try_write("a", || conn.execute("INSERT INTO crates VALUES ('a')", [])),
+            Some(1)
+        );
+    }
+}
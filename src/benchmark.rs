@@ -0,0 +1,126 @@
+//! The `benchmark` subcommand: time the real metadata, clone and database code on a
+//! small fixed set of crates, to compare settings on this machine and network.
+
+use anyhow::{Context, Result};
+use crates_io_api::SyncClient;
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::status::CrateStatus;
+use crate::{clone, scan, schema, Args};
+
+/// Small-to-medium, long-lived crates with stable repositories.
+pub const SAMPLE: [&str; 10] = [
+    "cfg-if",
+    "itoa",
+    "memchr",
+    "log",
+    "bitflags",
+    "smallvec",
+    "once_cell",
+    "semver",
+    "anyhow",
+    "either",
+];
+
+/// Time spent in each phase, summed over the sample.
+#[derive(Default)]
+struct Timings {
+    metadata: Duration,
+    clone: Duration,
+    database: Duration,
+}
+
+/// Fetch, clone and record every crate in `crates` into a scratch directory `dir` and
+/// an in-memory database, using the clone settings in `args`, then print the timings.
+pub fn run(args: &Args, crates: &[String], dir: &Path) -> Result<()> {
+    let client = SyncClient::new(crate::USER_AGENT, Duration::from_millis(args.delay_ms))
+        .context("could not create crates.io API client")?;
+    let mut conn = Connection::open_in_memory()?;
+    if args.print_sql {
+        conn.trace(Some(crate::print_sql));
+    }
+    schema::migrate(&conn)?;
+    let clone_opts = crate::clone_options(args);
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("could not clear {}", dir.display()))?;
+    }
+    fs::create_dir_all(dir).with_context(|| format!("could not create {}", dir.display()))?;
+
+    let mut timings = Timings::default();
+    let mut cloned = 0u64;
+    let mut bytes = 0u64;
+    let started = Instant::now();
+    for name in crates {
+        let t = Instant::now();
+        let resp = client.get_crate(name);
+        timings.metadata += t.elapsed();
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(e) => {
+                warn!("failed to fetch metadata for {}: {}", name, e);
+                continue;
+            }
+        };
+
+        let t = Instant::now();
+        crate::store_metadata(&conn, name, &resp, args.compress_db)?;
+        timings.database += t.elapsed();
+
+        let Some(repo) = &resp.crate_data.repository else {
+            warn!("{} has no repository URL", name);
+            continue;
+        };
+        let dest = dir.join(name);
+        let t = Instant::now();
+        let result = clone::clone(name, repo, &dest, &clone_opts);
+        timings.clone += t.elapsed();
+        if let Err(e) = result {
+            warn!("failed to clone {}: {}", name, e);
+            continue;
+        }
+        cloned += 1;
+        bytes += scan::dir_size(&dest);
+
+        let t = Instant::now();
+        conn.execute(
+            "UPDATE crates SET status = ?2, repo_dir = ?3 WHERE name = ?1",
+            params![name, CrateStatus::Cloned, dest.to_string_lossy()],
+        )?;
+        timings.database += t.elapsed();
+    }
+    let total = started.elapsed();
+    fs::remove_dir_all(dir).ok();
+
+    let per_crate = |d: Duration| d / crates.len().max(1) as u32;
+    println!("{:<10} {:>10} {:>12}", "phase", "total", "per crate");
+    for (phase, d) in [
+        ("metadata", timings.metadata),
+        ("clone", timings.clone),
+        ("database", timings.database),
+        ("overall", total),
+    ] {
+        println!(
+            "{:<10} {:>9.2}s {:>11.3}s",
+            phase,
+            d.as_secs_f64(),
+            per_crate(d).as_secs_f64()
+        );
+    }
+    println!();
+    println!(
+        "{} of {} crates cloned, {:.1} MiB",
+        cloned,
+        crates.len(),
+        bytes as f64 / (1024.0 * 1024.0)
+    );
+    println!(
+        "{:.2} crates/s overall, {:.2} MiB/s while cloning",
+        crates.len() as f64 / total.as_secs_f64(),
+        bytes as f64 / (1024.0 * 1024.0) / timings.clone.as_secs_f64().max(f64::EPSILON)
+    );
+    Ok(())
+}
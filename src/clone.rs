@@ -0,0 +1,834 @@
+//! Cloning repositories with git2, including the credentials dance.
+
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+    Cred, CredentialType, ErrorClass, ErrorCode, FetchOptions, RemoteCallbacks, Repository,
+};
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tracing::{debug, trace};
+
+/// Credentials offered to repositories that ask for authentication.
+#[derive(Clone)]
+pub struct Auth {
+    pub ssh_key: Option<PathBuf>,
+    pub token: Option<String>,
+    pub max_attempts: u32,
+}
+
+/// Transport to prefer for hosts whose URLs we know how to rewrite.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Https,
+    Git,
+    Ssh,
+}
+
+/// Everything that shapes how a single repository gets cloned.
+#[derive(Clone)]
+pub struct CloneOptions {
+    pub auth: Auth,
+    /// Bare repository used as a shared alternates object store, if any.
+    pub shared_objects: Option<PathBuf>,
+    pub protocol: Option<Protocol>,
+    /// Check files out byte for byte, with no line-ending or other conversions.
+    pub exact_bytes: bool,
+    /// Extra environment for the `git` processes that clone with the CLI; libgit2
+    /// clones don't run git, so they ignore it.
+    pub git_env: Vec<(String, String)>,
+}
+
+impl CloneOptions {
+    /// A [`git_command`] with [`CloneOptions::git_env`] added.
+    fn git_command(&self) -> Command {
+        let mut cmd = git_command();
+        cmd.envs(self.git_env.iter().map(|(k, v)| (k, v)));
+        cmd
+    }
+}
+
+/// A successful clone, along with the URL it was actually cloned from.
+pub struct Cloned {
+    pub repo: Repository,
+    pub url: String,
+    /// The clone was finished from an interrupted earlier attempt rather than made fresh.
+    pub resumed: bool,
+    /// `url` is where the crate's repository URL redirected to.
+    pub redirected: bool,
+}
+
+impl Cloned {
+    /// The transport the clone went over: `https`, `git`, `ssh`, `file`, ...
+    pub fn protocol(&self) -> &str {
+        match self.url.split_once("://") {
+            Some((scheme, _)) => scheme,
+            None if self.url.contains('@') => "ssh",
+            None => "file",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CloneError {
+    /// Every credential we had was rejected, or we ran out of attempts.
+    AuthFailed {
+        attempts: u32,
+    },
+    Git(git2::Error),
+    /// The `git` CLI failed; holds the tail of its stderr.
+    Cli(String),
+    /// Moving, copying or removing a checkout on disk failed.
+    Io(io::Error),
+    /// The directory already at the destination is a different repository, or not a
+    /// clone of anything.
+    WrongCheckout(String),
+}
+
+impl fmt::Display for CloneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloneError::AuthFailed { attempts } => {
+                write!(f, "authentication failed after {} attempt(s)", attempts)
+            }
+            CloneError::Git(e) => e.fmt(f),
+            CloneError::Cli(msg) | CloneError::WrongCheckout(msg) => f.write_str(msg),
+            CloneError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CloneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CloneError::Git(e) => Some(e),
+            CloneError::Io(e) => Some(e),
+            CloneError::AuthFailed { .. } | CloneError::Cli(_) | CloneError::WrongCheckout(_) => {
+                None
+            }
+        }
+    }
+}
+
+impl CloneError {
+    /// The clone failed because the disk filled up.
+    pub fn is_disk_full(&self) -> bool {
+        let msg = match self {
+            CloneError::AuthFailed { .. } | CloneError::WrongCheckout(_) => return false,
+            CloneError::Io(e) => return e.kind() == io::ErrorKind::StorageFull,
+            CloneError::Git(e) => e.message(),
+            CloneError::Cli(msg) => msg,
+        };
+        msg.contains("No space left on device")
+    }
+
+    /// The clone failed because the host's certificate couldn't be verified, or the TLS
+    /// handshake failed some other way.
+    pub fn is_tls_error(&self) -> bool {
+        match self {
+            CloneError::AuthFailed { .. } | CloneError::Io(_) | CloneError::WrongCheckout(_) => {
+                false
+            }
+            CloneError::Git(e) => {
+                e.code() == ErrorCode::Certificate || e.class() == ErrorClass::Ssl
+            }
+            CloneError::Cli(msg) => {
+                msg.contains("SSL certificate problem")
+                    || msg.contains("server certificate verification failed")
+                    || msg.contains("SSL_connect")
+                    || msg.contains("TLS connection")
+            }
+        }
+    }
+
+    /// libgit2's raw error code and class (`git_error_code`, `git_error_t`), where there
+    /// is one. Authentication failures report `GIT_EAUTH` with no class.
+    pub fn git_code(&self) -> Option<(i32, u32)> {
+        match self {
+            CloneError::AuthFailed { .. } => Some((ErrorCode::Auth as i32, 0)),
+            CloneError::Git(e) => Some((e.raw_code(), e.raw_class())),
+            CloneError::Cli(_) | CloneError::Io(_) | CloneError::WrongCheckout(_) => None,
+        }
+    }
+}
+
+/// The credential sources we try, in order. Each is offered at most once per clone,
+/// which is what keeps libgit2 from calling back forever with the same rejected key.
+#[derive(Clone, Copy)]
+enum Method {
+    Agent,
+    Key,
+    Token,
+    Anonymous,
+}
+
+const METHODS: [Method; 4] = [Method::Agent, Method::Key, Method::Token, Method::Anonymous];
+
+/// Where a clone into `dest` is made before it's moved into place: a sibling named
+/// `<dest>.tmp-<pid>`, so it's on the same filesystem and the move is a rename.
+fn staging_dir(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".tmp-{}", std::process::id()));
+    dest.with_file_name(name)
+}
+
+/// Staging directories left next to `dest` by earlier runs that stopped mid-clone.
+fn leftover_staging_dirs(dest: &Path) -> Vec<PathBuf> {
+    let (Some(parent), Some(name)) = (dest.parent(), dest.file_name()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.tmp-", name.to_string_lossy());
+    let Ok(entries) = fs::read_dir(parent) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// An interrupted earlier clone for `dest`: a leftover staging directory, or `dest`
+/// itself when it was cloned into directly by an older version.
+fn interrupted_clone(dest: &Path) -> Option<PathBuf> {
+    if dest.join(".git").is_dir() {
+        return Some(dest.to_path_buf());
+    }
+    leftover_staging_dirs(dest)
+        .into_iter()
+        .find(|dir| dir.join(".git").is_dir())
+}
+
+/// Whether there's a clone for `dest` we started earlier and can try to finish with
+/// [`clone`]. Clones into the shared object store are always made from scratch.
+pub fn is_resumable(dest: &Path, opts: &CloneOptions) -> bool {
+    opts.shared_objects.is_none() && interrupted_clone(dest).is_some()
+}
+
+/// Clone `url` into `dest` for crate `name`.
+///
+/// The clone is made in a staging directory next to `dest` and only renamed to `dest`
+/// once it has succeeded, so `dest` never holds a partial checkout; on failure the
+/// staging directory is removed.
+///
+/// With a preferred protocol, a recognised forge URL is rewritten to it first; if that
+/// clone fails we fall back to `url` as given.
+///
+/// If an interrupted clone of the same URL is lying around (see [`is_resumable`]), it
+/// is resumed instead, and only started over if that fails.
+pub fn clone(
+    name: &str,
+    url: &str,
+    dest: &Path,
+    opts: &CloneOptions,
+) -> Result<Cloned, CloneError> {
+    let staging = staging_dir(dest);
+    let interrupted = interrupted_clone(dest).filter(|_| opts.shared_objects.is_none());
+    if let Some(dir) = &interrupted {
+        fs::rename(dir, &staging).map_err(CloneError::Io)?;
+    }
+    for stale in leftover_staging_dirs(dest) {
+        if stale != staging {
+            debug!("removing stale staging directory {}", stale.display());
+            fs::remove_dir_all(&stale).ok();
+        }
+    }
+
+    match clone_staged(name, url, &staging, interrupted.is_some(), opts) {
+        Ok(cloned) => {
+            drop(cloned.repo);
+            publish(&staging, dest)?;
+            Ok(Cloned {
+                repo: Repository::open(dest).map_err(CloneError::Git)?,
+                ..cloned
+            })
+        }
+        Err(e) => {
+            if staging.exists() {
+                fs::remove_dir_all(&staging).ok();
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Take over a checkout something else already made at `dest`, for
+/// `--clone-into-existing`. It must be a git repository whose `origin` is `url`, as
+/// compared by [`normalize_url`].
+pub fn adopt(dest: &Path, url: &str) -> Result<Cloned, CloneError> {
+    let repo = Repository::open(dest).map_err(CloneError::Git)?;
+    let origin = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().map(str::to_owned));
+    let Some(origin) = origin else {
+        return Err(CloneError::WrongCheckout(format!(
+            "{} has no origin remote",
+            dest.display()
+        )));
+    };
+    if normalize_url(&origin) != normalize_url(url) {
+        return Err(CloneError::WrongCheckout(format!(
+            "{} is a clone of {}, not {}",
+            dest.display(),
+            origin,
+            url
+        )));
+    }
+    Ok(Cloned {
+        repo,
+        url: origin,
+        resumed: false,
+        redirected: false,
+    })
+}
+
+/// Move the finished clone in `staging` to `dest`. When the two turn out to be on
+/// different filesystems (`dest` is a mount point, say) the rename can't work, so the
+/// clone is copied over instead.
+fn publish(staging: &Path, dest: &Path) -> Result<(), CloneError> {
+    let moved = match fs::rename(staging, dest) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            debug!("{} is on another filesystem; copying", dest.display());
+            copy_dir(staging, dest).and_then(|()| fs::remove_dir_all(staging))
+        }
+        moved => moved,
+    };
+    moved.map_err(CloneError::Io)
+}
+
+/// Copy the tree at `from` to `to`, recreating symlinks rather than following them.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if kind.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+            #[cfg(not(unix))]
+            fs::copy(entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// The body of [`clone`], working in `dest` (the staging directory). `resumable` says
+/// `dest` already holds an interrupted clone.
+fn clone_staged(
+    name: &str,
+    url: &str,
+    dest: &Path,
+    resumable: bool,
+    opts: &CloneOptions,
+) -> Result<Cloned, CloneError> {
+    let rewritten = opts.protocol.and_then(|p| rewrite_url(url, p));
+
+    if resumable {
+        let urls = [Some(url), rewritten.as_deref()];
+        match resume(dest, &urls, opts) {
+            Ok(cloned) => return Ok(cloned),
+            Err(e) => {
+                debug!("can't resume clone of {} ({}); starting over", name, e);
+                fs::remove_dir_all(dest).map_err(CloneError::Io)?;
+            }
+        }
+    }
+
+    if let Some(rewritten) = rewritten {
+        match clone_from(name, &rewritten, dest, opts) {
+            Ok(repo) => {
+                return Ok(Cloned {
+                    repo,
+                    url: rewritten,
+                    resumed: false,
+                    redirected: false,
+                })
+            }
+            Err(e) => {
+                debug!(
+                    "clone of {} via {} failed ({}); retrying {}",
+                    name, rewritten, e, url
+                );
+                if dest.exists() {
+                    fs::remove_dir_all(dest).map_err(CloneError::Io)?;
+                }
+            }
+        }
+    }
+    let err = match clone_from(name, url, dest, opts) {
+        Ok(repo) => {
+            return Ok(Cloned {
+                repo,
+                url: url.to_owned(),
+                resumed: false,
+                redirected: false,
+            })
+        }
+        Err(e) => e,
+    };
+
+    // Vanity URLs and shortlinks redirect in ways libgit2 won't always follow, so work
+    // out where they really lead and try that instead.
+    if !looks_like_redirect(&err) {
+        return Err(err);
+    }
+    let Some(resolved) = resolve_redirect(url).filter(|r| r != url) else {
+        return Err(err);
+    };
+    debug!("{} redirects to {}; retrying there", url, resolved);
+    if dest.exists() {
+        fs::remove_dir_all(dest).map_err(CloneError::Io)?;
+    }
+    let repo = clone_from(name, &resolved, dest, opts)?;
+    Ok(Cloned {
+        repo,
+        url: resolved,
+        resumed: false,
+        redirected: true,
+    })
+}
+
+/// Whether a failed clone might succeed against wherever its URL redirects to.
+fn looks_like_redirect(err: &CloneError) -> bool {
+    let msg = match err {
+        CloneError::AuthFailed { .. } | CloneError::Io(_) | CloneError::WrongCheckout(_) => {
+            return false
+        }
+        CloneError::Git(e) => e.message().to_ascii_lowercase(),
+        CloneError::Cli(msg) => msg.to_ascii_lowercase(),
+    };
+    msg.contains("redirect")
+        || msg.contains("unexpected http status code: 3")
+        || msg.contains("invalid content-type")
+}
+
+/// Follow the HTTP redirects of `url` and return the URL they end at. Some hosts refuse
+/// HEAD, so a GET is tried if it fails.
+fn resolve_redirect(url: &str) -> Option<String> {
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return None;
+    }
+    let client = crate::tls::http_client()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .ok()?;
+    let response = client
+        .head(url)
+        .send()
+        .ok()
+        .filter(|r| r.status().is_success())
+        .or_else(|| client.get(url).send().ok())?;
+    Some(response.url().as_str().to_owned())
+}
+
+/// Finish an interrupted clone in `dest` whose origin is one of `urls`.
+///
+/// Git discards a pack that was cut off mid-transfer, so this only saves work when the
+/// earlier attempt got its objects (and so a resolvable HEAD) before it stopped, e.g.
+/// during a long checkout. The fetch then only downloads what's new since, the checked
+/// out branch is moved to the fetched tip, and the working tree is rebuilt.
+fn resume(dest: &Path, urls: &[Option<&str>], opts: &CloneOptions) -> Result<Cloned, CloneError> {
+    let repo = Repository::open(dest).map_err(CloneError::Git)?;
+    let remote = repo.find_remote("origin").map_err(CloneError::Git)?;
+    let url = remote.url().unwrap_or_default().to_owned();
+    if !urls.contains(&Some(url.as_str())) {
+        return Err(CloneError::WrongCheckout(format!(
+            "partial clone is of {}",
+            url
+        )));
+    }
+    // HEAD must already point at a commit, or there's nothing worth keeping.
+    repo.head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(CloneError::Git)?;
+    drop(remote);
+    update_from_origin(&repo, &url, opts, "bugbot: resume clone")?;
+
+    Ok(Cloned {
+        repo,
+        url,
+        resumed: true,
+        redirected: false,
+    })
+}
+
+/// Point the origin of the checkout at `dest` to `url`, where its repository moved,
+/// and bring the checkout up to date from there. The old origin is put back if that
+/// fails.
+pub fn relocate(dest: &Path, url: &str, opts: &CloneOptions) -> Result<(), CloneError> {
+    let repo = Repository::open(dest).map_err(CloneError::Git)?;
+    let old = repo
+        .find_remote("origin")
+        .map_err(CloneError::Git)?
+        .url()
+        .unwrap_or_default()
+        .to_owned();
+    repo.remote_set_url("origin", url)
+        .map_err(CloneError::Git)?;
+    let updated = update_from_origin(&repo, url, opts, "bugbot: repository moved");
+    if updated.is_err() {
+        repo.remote_set_url("origin", &old).ok();
+    }
+    updated
+}
+
+/// Fetch `origin` (at `url`), move the checked-out branch to the fetched tip and
+/// rebuild the working tree. A detached HEAD stays where it is.
+fn update_from_origin(
+    repo: &Repository,
+    url: &str,
+    opts: &CloneOptions,
+    log_message: &str,
+) -> Result<(), CloneError> {
+    let auth = &opts.auth;
+    let mut remote = repo.find_remote("origin").map_err(CloneError::Git)?;
+    let state = CredentialState::default();
+    let mut fetch = FetchOptions::new();
+    fetch.remote_callbacks(remote_callbacks(url, auth, &state));
+    remote
+        .fetch::<&str>(&[], Some(&mut fetch), None)
+        .map_err(|e| state.error(e, auth))?;
+    drop(fetch);
+    drop(remote);
+
+    {
+        let head = repo.head().map_err(CloneError::Git)?;
+        let upstream = head.shorthand().and_then(|branch| {
+            repo.refname_to_id(&format!("refs/remotes/origin/{branch}"))
+                .ok()
+        });
+        if let (Some(name), Some(upstream)) = (head.name(), upstream) {
+            repo.reference(name, upstream, true, log_message)
+                .map_err(CloneError::Git)?;
+        }
+    }
+    let mut checkout = CheckoutBuilder::new();
+    checkout.force().disable_filters(opts.exact_bytes);
+    repo.checkout_head(Some(&mut checkout))
+        .map_err(CloneError::Git)
+}
+
+fn clone_from(
+    name: &str,
+    url: &str,
+    dest: &Path,
+    opts: &CloneOptions,
+) -> Result<Repository, CloneError> {
+    match &opts.shared_objects {
+        Some(store) => clone_shared(name, url, dest, store, opts),
+        None => clone_repo(url, dest, &opts.auth, opts.exact_bytes),
+    }
+}
+
+/// Hosts whose repository URLs we know the shape of, and so can rewrite.
+const REWRITABLE_HOSTS: [&str; 4] = ["github.com", "gitlab.com", "codeberg.org", "bitbucket.org"];
+
+/// The host and `owner/name` path of an `https://` URL on a known forge, with any
+/// trailing `/tree/main/...` and `.git` removed.
+fn forge_repo(url: &str) -> Option<(String, String)> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let (host, path) = rest.split_once('/')?;
+    let host = host.to_ascii_lowercase();
+    if !REWRITABLE_HOSTS.contains(&host.as_str()) {
+        return None;
+    }
+
+    // GitLab allows nested groups and marks the end of the project path with `/-/`;
+    // everywhere else a repository is exactly `owner/name`.
+    let path = if host == "gitlab.com" {
+        path.split("/-/").next()?.trim_end_matches('/').to_owned()
+    } else {
+        let mut segments = path.split('/').filter(|s| !s.is_empty());
+        format!("{}/{}", segments.next()?, segments.next()?)
+    };
+    let path = path.strip_suffix(".git").unwrap_or(&path);
+    if path.is_empty() {
+        return None;
+    }
+    Some((host, path.to_owned()))
+}
+
+/// Rewrite an `https://` URL on a known forge to `protocol`. `None` when the URL isn't
+/// one we recognise or is already in that form.
+fn rewrite_url(url: &str, protocol: Protocol) -> Option<String> {
+    if protocol == Protocol::Https {
+        return None;
+    }
+    let (host, path) = forge_repo(url)?;
+    Some(match protocol {
+        Protocol::Git => format!("git://{}/{}.git", host, path),
+        Protocol::Ssh => format!("git@{}:{}.git", host, path),
+        Protocol::Https => unreachable!(),
+    })
+}
+
+/// A key under which different spellings of the same repository URL compare equal:
+/// scheme, trailing slashes and `.git` are dropped, and forge URLs are cut down to the
+/// repository itself (forges treat owner and name case-insensitively, so those are
+/// lowercased too).
+pub fn normalize_url(url: &str) -> String {
+    if let Some((host, path)) = forge_repo(url.trim()) {
+        return format!("{}/{}", host, path.to_ascii_lowercase());
+    }
+    let url = url.trim().trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    match rest.split_once('/') {
+        Some((host, path)) => format!("{}/{}", host.to_ascii_lowercase(), path),
+        None => rest.to_ascii_lowercase(),
+    }
+}
+
+/// How a clone's credential callback is getting on, kept outside the callback so the
+/// final error can tell an authentication failure from any other.
+#[derive(Default)]
+struct CredentialState {
+    attempts: Cell<u32>,
+    next_method: Cell<usize>,
+    exhausted: Cell<bool>,
+}
+
+impl CredentialState {
+    /// Classify a failed clone or fetch.
+    fn error(&self, e: git2::Error, auth: &Auth) -> CloneError {
+        if self.exhausted.get() || e.code() == ErrorCode::Auth {
+            CloneError::AuthFailed {
+                attempts: self.attempts.get().min(auth.max_attempts),
+            }
+        } else {
+            CloneError::Git(e)
+        }
+    }
+}
+
+/// Callbacks that answer credential prompts from `auth` and trace transfer progress.
+fn remote_callbacks<'a>(
+    url: &'a str,
+    auth: &'a Auth,
+    state: &'a CredentialState,
+) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|_url, username_from_url, allowed| {
+        state.attempts.set(state.attempts.get() + 1);
+        if state.attempts.get() > auth.max_attempts {
+            state.exhausted.set(true);
+            return Err(git2::Error::from_str("too many authentication attempts"));
+        }
+        let user = username_from_url.unwrap_or("git");
+
+        // SSH transports first ask for a username when the URL has none.
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(user);
+        }
+
+        while let Some(&method) = METHODS.get(state.next_method.get()) {
+            state.next_method.set(state.next_method.get() + 1);
+            let cred = match method {
+                Method::Agent if allowed.contains(CredentialType::SSH_KEY) => {
+                    Cred::ssh_key_from_agent(user)
+                }
+                Method::Key if allowed.contains(CredentialType::SSH_KEY) => match &auth.ssh_key {
+                    Some(key) => Cred::ssh_key(user, None, key, None),
+                    None => continue,
+                },
+                Method::Token if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) => {
+                    match &auth.token {
+                        Some(token) => Cred::userpass_plaintext(
+                            username_from_url.unwrap_or("x-access-token"),
+                            token,
+                        ),
+                        None => continue,
+                    }
+                }
+                Method::Anonymous if allowed.contains(CredentialType::DEFAULT) => Cred::default(),
+                _ => continue,
+            };
+            // A source that can't even build a credential (no agent running, unreadable
+            // key) just moves us on to the next one.
+            if cred.is_ok() {
+                return cred;
+            }
+        }
+
+        state.exhausted.set(true);
+        Err(git2::Error::from_str("no more credentials to try"))
+    });
+
+    callbacks.certificate_check(crate::tls::check_git_certificate);
+
+    let mut last_progress = Instant::now();
+    let mut reported_done = false;
+    callbacks.transfer_progress(move |progress| {
+        let done = progress.received_objects() == progress.total_objects();
+        if (done && !reported_done) || last_progress.elapsed() >= Duration::from_secs(1) {
+            last_progress = Instant::now();
+            reported_done = done;
+            trace!(
+                "{}: {}/{} objects, {} bytes",
+                url,
+                progress.received_objects(),
+                progress.total_objects(),
+                progress.received_bytes()
+            );
+        }
+        true
+    });
+    callbacks
+}
+
+/// Clone `url` into `dest`, answering credential prompts from `auth`.
+fn clone_repo(
+    url: &str,
+    dest: &Path,
+    auth: &Auth,
+    exact_bytes: bool,
+) -> Result<Repository, CloneError> {
+    let state = CredentialState::default();
+    let mut fetch = FetchOptions::new();
+    fetch.remote_callbacks(remote_callbacks(url, auth, &state));
+    let mut checkout = CheckoutBuilder::new();
+    checkout.disable_filters(exact_bytes);
+
+    let repo = RepoBuilder::new()
+        .fetch_options(fetch)
+        .with_checkout(checkout)
+        .clone(url, dest)
+        .map_err(|e| state.error(e, auth))?;
+    if exact_bytes {
+        keep_exact_bytes(&repo)?;
+    }
+    Ok(repo)
+}
+
+/// Make every later checkout in `repo`, by libgit2 or the `git` CLI, write blobs byte
+/// for byte: no line-ending conversion, `ident` expansion or re-encoding, whatever the
+/// repository's `.gitattributes` or the user's git config ask for.
+fn keep_exact_bytes(repo: &Repository) -> Result<(), CloneError> {
+    let info = repo.path().join("info");
+    fs::create_dir_all(&info)
+        .and_then(|()| {
+            fs::write(
+                info.join("attributes"),
+                "* -text -ident -working-tree-encoding\n",
+            )
+        })
+        .map_err(CloneError::Io)?;
+    repo.config()
+        .and_then(|mut config| config.set_bool("core.autocrlf", false))
+        .map_err(CloneError::Git)
+}
+
+/// Clone through the `git` CLI with `--reference-if-able`, so objects already in the
+/// shared `store` are neither downloaded nor written again.
+///
+/// libgit2 can read from alternates but won't advertise them during fetch negotiation,
+/// so it would still download everything; that's why this path shells out to git.
+/// After the clone, the new objects are fetched into the store under
+/// `refs/bugbot/<name>/` (which keeps them reachable there) and the clone is repacked
+/// with `-l` to drop its now-redundant local copies.
+fn clone_shared(
+    name: &str,
+    url: &str,
+    dest: &Path,
+    store: &Path,
+    opts: &CloneOptions,
+) -> Result<Repository, CloneError> {
+    let exact_bytes = opts.exact_bytes;
+    if !store.join("objects").is_dir() {
+        Repository::init_bare(store).map_err(CloneError::Git)?;
+    }
+
+    let mut clone = opts.git_command();
+    clone.arg("clone").arg("--quiet");
+    if exact_bytes {
+        // Checked out below, once the conversions are turned off.
+        clone.arg("--no-checkout");
+    }
+    clone
+        .arg("--reference-if-able")
+        .arg(store)
+        .arg("--")
+        .arg(url)
+        .arg(dest);
+    run_git(clone)?;
+    if exact_bytes {
+        keep_exact_bytes(&Repository::open(dest).map_err(CloneError::Git)?)?;
+        let mut checkout = opts.git_command();
+        checkout
+            .arg("-C")
+            .arg(dest)
+            .args(["reset", "--hard", "--quiet"]);
+        run_git(checkout)?;
+    }
+
+    let mut absorb = opts.git_command();
+    absorb
+        .arg("-C")
+        .arg(store)
+        .args(["fetch", "--quiet", "--no-tags"])
+        .arg(dest)
+        .arg(format!("+refs/heads/*:refs/bugbot/{name}/heads/*"))
+        .arg(format!("+refs/tags/*:refs/bugbot/{name}/tags/*"));
+    run_git(absorb)?;
+
+    let mut repack = opts.git_command();
+    repack
+        .arg("-C")
+        .arg(dest)
+        .args(["repack", "-a", "-d", "-l", "-q"]);
+    run_git(repack)?;
+
+    Repository::open(dest).map_err(CloneError::Git)
+}
+
+/// A `git` invocation that fails instead of prompting when credentials are needed,
+/// that leaves LFS pointer files alone rather than downloading what they point at, and
+/// that uses our TLS settings.
+pub fn git_command() -> Command {
+    let mut cmd = Command::new("git");
+    cmd.env("GIT_TERMINAL_PROMPT", "0");
+    cmd.env("GIT_LFS_SKIP_SMUDGE", "1");
+    crate::tls::configure_git(&mut cmd);
+    cmd
+}
+
+pub fn run_git(mut cmd: Command) -> Result<(), CloneError> {
+    let output = cmd
+        .output()
+        .map_err(|e| CloneError::Cli(format!("failed to run git: {}", e)))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("Authentication failed")
+        || stderr.contains("could not read Username")
+        || stderr.contains("Permission denied (publickey")
+    {
+        return Err(CloneError::AuthFailed { attempts: 1 });
+    }
+    let last = stderr
+        .lines()
+        .rev()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("");
+    Err(CloneError::Cli(format!(
+        "git exited with {}: {}",
+        output.status,
+        last.trim()
+    )))
+}
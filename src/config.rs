@@ -0,0 +1,255 @@
+//! The optional TOML config file, whose values become defaults for the command line.
+
+use crate::clone::Protocol;
+use crate::console::LogFormat;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Everything a config file may set. Keys are the flag names with underscores
+/// (`delay_ms = 500` for `--delay-ms 500`); flags given on the command line win.
+#[derive(Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub output: Option<PathBuf>,
+    pub output_url: Option<String>,
+    pub delay_ms: Option<u64>,
+    /// How many times `-v` is given.
+    pub verbose: Option<u8>,
+    /// How many times `-q` is given.
+    pub quiet: Option<u8>,
+    pub print_sql: Option<bool>,
+    pub log_format: Option<LogFormat>,
+    pub ssh_key: Option<PathBuf>,
+    pub git_token: Option<String>,
+    pub max_auth_attempts: Option<u32>,
+    pub shared_objects: Option<PathBuf>,
+    #[serde(alias = "skip_unreachable_hosts")]
+    pub preflight_hosts: Option<bool>,
+    pub ca_bundle: Option<PathBuf>,
+    pub insecure_tls: Option<bool>,
+    pub clone_protocol: Option<Protocol>,
+    /// `KEY=VALUE` pairs, like `--clone-env`.
+    pub clone_env: Option<Vec<String>>,
+    pub normalize_line_endings: Option<bool>,
+    pub checkout_version_tag: Option<bool>,
+    pub include_yanked_versions: Option<bool>,
+    pub only_newest_version_with_tag: Option<bool>,
+    pub verify_head_matches_tag: Option<bool>,
+    pub tags_as_versions: Option<bool>,
+    pub skip_vendored: Option<bool>,
+    pub fetch_lfs: Option<bool>,
+    pub fetch_tarball: Option<bool>,
+    pub tarball_only: Option<bool>,
+    pub compare_tarball_to_git: Option<bool>,
+    pub remove_empty_repos: Option<bool>,
+    pub clone_into_existing: Option<bool>,
+    pub detect_moved_repos: Option<bool>,
+    pub min_edition: Option<u16>,
+    pub min_rust_version: Option<String>,
+    pub remove_filtered: Option<bool>,
+    pub keep_going_on_panic: Option<bool>,
+    pub output_manifest_per_crate: Option<bool>,
+    pub max_crates_per_repo: Option<u64>,
+    pub fetch_owners: Option<bool>,
+    pub checkpoint_interval: Option<u64>,
+    pub checkpoint_every: Option<u64>,
+    pub state_only: Option<bool>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub from_db_dump: Option<PathBuf>,
+    pub skip_no_repo_rows: Option<bool>,
+    pub compress_db: Option<bool>,
+    pub vacuum: Option<bool>,
+    pub backup: Option<PathBuf>,
+    pub only_changed: Option<bool>,
+    pub background_index_update: Option<bool>,
+    pub diff_against: Option<PathBuf>,
+    pub since_crate: Option<String>,
+    pub until_crate: Option<String>,
+    /// A shard such as `0/4`.
+    pub shard: Option<String>,
+    pub owner: Option<String>,
+    pub crates_file: Option<PathBuf>,
+    pub repo_url_overrides: Option<PathBuf>,
+    pub clone_rules: Option<PathBuf>,
+    pub license_filter: Option<String>,
+    pub post_clone_hook: Option<String>,
+    pub hook_log: Option<PathBuf>,
+    pub scan_advisories: Option<bool>,
+    pub advisory_db: Option<PathBuf>,
+    pub scan_unsafe: Option<bool>,
+    pub scan_tests: Option<bool>,
+    pub require_tests: Option<bool>,
+    pub fetch_dependents: Option<bool>,
+    pub min_dependents: Option<u64>,
+    pub dependents_max_age_days: Option<u64>,
+    pub max_api_calls: Option<u64>,
+    pub refetch_metadata_after: Option<String>,
+    /// A duration such as `90m`, `12h` or `2d`.
+    pub max_runtime: Option<String>,
+    pub pause_file: Option<PathBuf>,
+    pub wait_on_disk_full: Option<bool>,
+    pub min_free_mb: Option<u64>,
+    pub progress_socket: Option<PathBuf>,
+    pub statsd: Option<String>,
+    pub statsd_prefix: Option<String>,
+    pub preflight: Option<bool>,
+    pub probe_repo: Option<String>,
+    pub always_exit_zero: Option<bool>,
+}
+
+/// The value of `--config` in `args`, found before clap runs so that the file can
+/// supply clap's defaults.
+pub fn path_from_args(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+pub fn load(path: &Path) -> Result<Config> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+}
+
+/// Make every value set in `config` the default of the argument with the same name.
+/// Values are handed to clap as strings, so they're validated exactly like flags.
+pub fn apply(mut cmd: clap::Command, config: &Config) -> Result<clap::Command> {
+    let toml::Value::Table(table) = toml::Value::try_from(config)? else {
+        bail!("config did not serialize to a table");
+    };
+    for (key, value) in table {
+        let values: Vec<String> = match value {
+            toml::Value::Array(items) => items.into_iter().map(plain).collect(),
+            other => vec![plain(other)],
+        };
+        cmd = cmd.mut_arg(key, |arg| arg.default_values(values));
+    }
+    Ok(cmd)
+}
+
+/// A scalar as it would be written on the command line: strings without their quotes.
+fn plain(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// The JSON schema of the config file, for editors and other tooling.
+#[cfg(feature = "schema")]
+pub fn schema() -> String {
+    let schema = schemars::schema_for!(Config);
+    serde_json::to_string_pretty(&schema).expect("schemas always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn parse(config: &str, args: &[&str]) -> crate::Args {
+        let config: Config = toml::from_str(config).unwrap();
+        let cmd = apply(crate::Args::command(), &config).unwrap();
+        let mut matches = cmd
+            .try_get_matches_from(std::iter::once("bugbot9000").chain(args.iter().copied()))
+            .unwrap();
+        crate::Args::from_arg_matches_mut(&mut matches).unwrap()
+    }
+
+    #[test]
+    fn arrays_become_several_defaults() {
+        let args = parse(
+            r#"
+            delay_ms = 250
+            verbose = 2
+            clone_env = ["GIT_ASKPASS=/bin/true", "NO_PROXY=a,b"]
+            "#,
+            &[],
+        );
+        assert_eq!(args.delay_ms, 250);
+        assert_eq!(args.verbose, 2);
+        assert_eq!(
+            args.clone_env,
+            [
+                ("GIT_ASKPASS".to_owned(), "/bin/true".to_owned()),
+                ("NO_PROXY".to_owned(), "a,b".to_owned()),
+            ]
+        );
+    }
+
+    /// A deserializer that only records the field names of the struct asked for.
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for FieldNames<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(
+            self,
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: serde::de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(serde::de::Error::custom("only the field names were wanted"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+            byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+            identifier ignored_any
+        }
+    }
+
+    /// `apply` panics on a key that isn't an argument, so every field has to be one.
+    /// Aliases are listed too, but only ever read: they serialize as the field's name.
+    #[test]
+    fn every_key_is_an_argument() {
+        let mut keys: &[&str] = &[];
+        Config::deserialize(FieldNames(&mut keys)).ok();
+        assert!(!keys.is_empty());
+        let cmd = crate::Args::command();
+        for key in keys {
+            assert!(
+                cmd.get_arguments().any(|arg| arg.get_id() == key
+                    || arg
+                        .get_all_aliases()
+                        .into_iter()
+                        .flatten()
+                        .any(|alias| alias.replace('-', "_") == *key)),
+                "config key `{key}` is not an argument"
+            );
+        }
+    }
+
+    #[test]
+    fn flags_win_over_the_file() {
+        let args = parse(
+            r#"clone_env = ["A=1", "B=2"]"#,
+            &["--clone-env", "C=3", "--delay-ms", "5"],
+        );
+        assert_eq!(args.clone_env, [("C".to_owned(), "3".to_owned())]);
+        assert_eq!(args.delay_ms, 5);
+    }
+}
@@ -0,0 +1,156 @@
+//! The one line printed for each crate's result, in the format `--log-format` picks.
+
+#[cfg(unix)]
+use std::cell::RefCell;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+#[cfg(unix)]
+use crate::progress::ProgressSocket;
+use crate::scan;
+use crate::statsd::StatsD;
+use crate::status::CrateStatus;
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Log lines with a symbol for each outcome
+    Human,
+    /// Log lines without the symbols: `<status> <crate>[: <detail>]`
+    Plain,
+    /// `<crate>\t<status>\t<detail>` on stdout
+    Tsv,
+    /// One JSON object per crate on stdout
+    Json,
+}
+
+/// Where each crate's result goes: the console, and the progress socket and StatsD
+/// if there are any.
+pub struct Reporter {
+    format: LogFormat,
+    #[cfg(unix)]
+    socket: Option<RefCell<ProgressSocket>>,
+    statsd: Option<StatsD>,
+}
+
+impl Reporter {
+    pub fn new(format: LogFormat, statsd: Option<StatsD>) -> Self {
+        Reporter {
+            format,
+            #[cfg(unix)]
+            socket: None,
+            statsd,
+        }
+    }
+
+    /// Also send every result to the clients of `socket`.
+    #[cfg(unix)]
+    pub fn with_socket(self, socket: ProgressSocket) -> Self {
+        Reporter {
+            socket: Some(RefCell::new(socket)),
+            ..self
+        }
+    }
+
+    /// Report how long a successful clone into `dest` took, and how big it is. Only
+    /// StatsD takes these; the size isn't even measured without it.
+    pub fn clone_finished(&self, took: Duration, dest: &Path) {
+        if let Some(statsd) = &self.statsd {
+            statsd.timing("clone.duration", took);
+            statsd.count("clone.bytes", scan::dir_size(dest));
+        }
+    }
+
+    /// Report that `name` ended up in `status`. `detail` is the error for failures, the
+    /// checkout for shared repositories and name mismatches, and the reason for skips.
+    pub fn report(&self, name: &str, status: CrateStatus, detail: Option<&str>) {
+        print(self.format, name, status, detail);
+        #[cfg(unix)]
+        if let Some(socket) = &self.socket {
+            socket
+                .borrow_mut()
+                .send(&json_line(name, status, detail).to_string());
+        }
+        if let Some(statsd) = &self.statsd {
+            statsd.count("crates.processed", 1);
+            statsd.count(&format!("crates.{}", status.as_str()), 1);
+        }
+    }
+}
+
+/// Human and plain lines are logged, so `-q` hides all but the failures; TSV and JSON
+/// lines are always written to stdout, so they can be piped while `-q` keeps the rest
+/// of the log out of the way.
+fn print(format: LogFormat, name: &str, status: CrateStatus, detail: Option<&str>) {
+    match format {
+        LogFormat::Human => human(name, status, detail.unwrap_or_default()),
+        LogFormat::Plain => {
+            let line = match detail {
+                Some(detail) => format!("{} {}: {}", status.as_str(), name, detail),
+                None => format!("{} {}", status.as_str(), name),
+            };
+            match status {
+                CrateStatus::Failed | CrateStatus::Panicked | CrateStatus::MetadataError => {
+                    error!("{}", line)
+                }
+                CrateStatus::AuthRequired
+                | CrateStatus::TlsError
+                | CrateStatus::HeadTagMismatch
+                | CrateStatus::EmptyRepo
+                | CrateStatus::NameMismatch => warn!("{}", line),
+                CrateStatus::RepoCap
+                | CrateStatus::TooFewDependents
+                | CrateStatus::EditionFiltered
+                | CrateStatus::TestsFiltered
+                | CrateStatus::LicenseFiltered
+                | CrateStatus::Pending => debug!("{}", line),
+                _ => info!("{}", line),
+            }
+        }
+        LogFormat::Tsv => {
+            let detail = detail.unwrap_or_default().replace(['\t', '\n', '\r'], " ");
+            println!("{}\t{}\t{}", name, status.as_str(), detail);
+        }
+        LogFormat::Json => println!("{}", json_line(name, status, detail)),
+    }
+}
+
+fn json_line(name: &str, status: CrateStatus, detail: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "crate": name,
+        "status": status.as_str(),
+        "detail": detail,
+    })
+}
+
+fn human(name: &str, status: CrateStatus, detail: &str) {
+    match status {
+        CrateStatus::Cloned => info!("✓ cloned {}", name),
+        CrateStatus::Resumed => info!("✓ resumed clone of {}", name),
+        CrateStatus::RepoMoved => info!("↪ {} moved: {}", name, detail),
+        CrateStatus::SharedRepo => info!("✓ {} is in {}", name, detail),
+        CrateStatus::MetadataOk => info!("✓ fetched metadata for {}", name),
+        CrateStatus::TarballOk => info!("✓ downloaded {}", detail),
+        CrateStatus::NoRepo => info!("ℹ no repository URL for {}", name),
+        CrateStatus::EmptyRepo => warn!("∅ {} cloned but the repository has no commits", name),
+        CrateStatus::NameMismatch => warn!("≠ no Cargo.toml in {} names {}", detail, name),
+        CrateStatus::AuthRequired => warn!("🔒 {} requires credentials: {}", name, detail),
+        CrateStatus::HostUnreachable => warn!("⊘ skipping {}: {}", name, detail),
+        CrateStatus::TlsError => warn!("🔓 TLS failure cloning {}: {}", name, detail),
+        CrateStatus::Failed => error!("✗ failed to clone {}: {}", name, detail),
+        CrateStatus::Panicked => error!("💥 {} panicked: {}", name, detail),
+        CrateStatus::HeadTagMismatch => {
+            warn!("≠ {} checked out the wrong commit: {}", name, detail)
+        }
+        CrateStatus::MetadataError => error!("✗ failed to fetch metadata for {}: {}", name, detail),
+        CrateStatus::EditionFiltered | CrateStatus::TestsFiltered => {
+            info!("⤓ {} is below the bar: {}", name, detail)
+        }
+        CrateStatus::RepoCap
+        | CrateStatus::TooFewDependents
+        | CrateStatus::LicenseFiltered
+        | CrateStatus::Pending => debug!("skipping {}: {}", name, detail),
+    }
+}
@@ -0,0 +1,512 @@
+//! Crate metadata from a crates.io database dump (<https://static.crates.io/db-dump.tar.gz>),
+//! for `--from-db-dump`.
+//!
+//! The dump is a gzipped tarball of CSV files, one per table, under
+//! `<timestamp>/data/`. Only the tables the `crates` row needs are read, and each crate
+//! is served in the shape the API would have returned, so nothing past
+//! [`CrateMetadataSource`] can tell the difference.
+
+use crate::metadata::CrateMetadataSource;
+use anyhow::{anyhow, Context, Result};
+use crates_io_api::CrateResponse;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct CrateRow {
+    id: u64,
+    name: String,
+    created_at: String,
+    updated_at: String,
+    description: Option<String>,
+    documentation: Option<String>,
+    homepage: Option<String>,
+    repository: Option<String>,
+    /// Moved to `crate_downloads.csv` in newer dumps.
+    #[serde(default)]
+    downloads: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct VersionRow {
+    id: u64,
+    crate_id: u64,
+    num: String,
+    created_at: String,
+    updated_at: String,
+    /// Missing from older dumps.
+    #[serde(default)]
+    downloads: u64,
+    /// `t` or `f`, as PostgreSQL writes booleans.
+    yanked: String,
+    license: Option<String>,
+    crate_size: Option<u64>,
+    #[serde(default)]
+    rust_version: Option<String>,
+    #[serde(default)]
+    checksum: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CategoryRow {
+    id: u64,
+    category: String,
+    slug: String,
+    description: String,
+    crates_cnt: u64,
+    created_at: String,
+}
+
+#[derive(Deserialize)]
+struct CrateCategoryRow {
+    crate_id: u64,
+    category_id: u64,
+}
+
+#[derive(Deserialize)]
+struct CrateDownloadsRow {
+    crate_id: u64,
+    downloads: u64,
+}
+
+/// The directory holding `data/` in a dump unpacked into `path`: `path` itself, or the
+/// one `<timestamp>` directory in it, which is where `tar xzf` puts everything.
+fn unpacked_root(path: &Path) -> Result<PathBuf> {
+    let has_tables =
+        |dir: &Path| dir.join("data/crates.csv").is_file() || dir.join("crates.csv").is_file();
+    if has_tables(path) {
+        return Ok(path.to_path_buf());
+    }
+    let mut dumps = Vec::new();
+    for entry in fs::read_dir(path).with_context(|| format!("could not read {}", path.display()))? {
+        let dir = entry?.path();
+        if dir.is_dir() && has_tables(&dir) {
+            dumps.push(dir);
+        }
+    }
+    match dumps.len() {
+        0 | 1 => Ok(dumps.pop().unwrap_or_else(|| path.to_path_buf())),
+        _ => Err(anyhow!(
+            "{} holds {} dumps; pass the directory of the one to use",
+            path.display(),
+            dumps.len()
+        )),
+    }
+}
+
+#[derive(Default)]
+pub struct DbDump {
+    crates: HashMap<String, CrateRow>,
+    versions: HashMap<u64, Vec<VersionRow>>,
+    categories: HashMap<u64, CategoryRow>,
+    crate_categories: HashMap<u64, Vec<u64>>,
+    downloads: HashMap<u64, u64>,
+}
+
+const TABLES: &[&str] = &[
+    "crates.csv",
+    "versions.csv",
+    "categories.csv",
+    "crates_categories.csv",
+    "crate_downloads.csv",
+];
+
+impl DbDump {
+    /// Read the dump at `path`: either the `.tar.gz` as downloaded, or a directory it
+    /// was unpacked into.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut dump = DbDump::default();
+        if path.is_dir() {
+            let dir = unpacked_root(path)?;
+            for table in TABLES {
+                let file = [dir.join("data").join(table), dir.join(table)]
+                    .into_iter()
+                    .find(|file| file.is_file());
+                let Some(file) = file else {
+                    continue;
+                };
+                let reader = File::open(&file)
+                    .with_context(|| format!("could not open {}", file.display()))?;
+                dump.read_table(table, reader)
+                    .with_context(|| format!("could not read {}", file.display()))?;
+            }
+        } else {
+            let file =
+                File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+            let mut archive = tar::Archive::new(GzDecoder::new(file));
+            let bad = || format!("could not read {}", path.display());
+            for entry in archive.entries().with_context(bad)? {
+                let entry = entry.with_context(bad)?;
+                let entry_path = entry.path().with_context(bad)?.into_owned();
+                let in_data = entry_path
+                    .parent()
+                    .and_then(Path::file_name)
+                    .is_some_and(|dir| dir == "data");
+                let table = entry_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .filter(|name| in_data && TABLES.contains(&name.as_str()));
+                if let Some(table) = table {
+                    dump.read_table(&table, entry)
+                        .with_context(|| format!("could not read {} in the dump", table))?;
+                }
+            }
+        }
+        if dump.crates.is_empty() {
+            return Err(anyhow!("no crates.csv in {}", path.display()));
+        }
+        Ok(dump)
+    }
+
+    /// The number of crates in the dump.
+    pub fn len(&self) -> usize {
+        self.crates.len()
+    }
+
+    fn read_table(&mut self, table: &str, reader: impl Read) -> Result<()> {
+        let mut csv = csv::Reader::from_reader(reader);
+        match table {
+            "crates.csv" => {
+                for row in csv.deserialize::<CrateRow>() {
+                    let row = row?;
+                    self.crates.insert(row.name.clone(), row);
+                }
+            }
+            "versions.csv" => {
+                for row in csv.deserialize::<VersionRow>() {
+                    let row = row?;
+                    self.versions.entry(row.crate_id).or_default().push(row);
+                }
+            }
+            "categories.csv" => {
+                for row in csv.deserialize::<CategoryRow>() {
+                    let row = row?;
+                    self.categories.insert(row.id, row);
+                }
+            }
+            "crates_categories.csv" => {
+                for row in csv.deserialize::<CrateCategoryRow>() {
+                    let row = row?;
+                    self.crate_categories
+                        .entry(row.crate_id)
+                        .or_default()
+                        .push(row.category_id);
+                }
+            }
+            "crate_downloads.csv" => {
+                for row in csv.deserialize::<CrateDownloadsRow>() {
+                    let row = row?;
+                    self.downloads.insert(row.crate_id, row.downloads);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The API's `GET /crates/{name}` response body for `name`.
+    fn response(&self, krate: &CrateRow) -> Value {
+        let name = &krate.name;
+        let versions = self
+            .versions
+            .get(&krate.id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut by_semver: Vec<_> = versions
+            .iter()
+            .filter_map(|v| Some((semver::Version::parse(&v.num).ok()?, v)))
+            .collect();
+        by_semver.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let unyanked = || by_semver.iter().filter(|(_, v)| v.yanked != "t");
+        // Like the API, fall back to the newest yanked version when every one is.
+        let max_version = unyanked()
+            .chain(&by_semver)
+            .next()
+            .map(|(_, v)| v.num.as_str())
+            .unwrap_or("0.0.0");
+        let max_stable_version = unyanked()
+            .find(|(semver, _)| semver.pre.is_empty())
+            .map(|(_, v)| v.num.as_str());
+        let categories: Vec<&CategoryRow> = self
+            .crate_categories
+            .get(&krate.id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.categories.get(id))
+            .collect();
+
+        let api = format!("/api/v1/crates/{name}");
+        json!({
+            "crate": {
+                "id": name,
+                "name": name,
+                "description": krate.description,
+                "documentation": krate.documentation,
+                "homepage": krate.homepage,
+                "repository": krate.repository,
+                "downloads": krate.downloads.or(self.downloads.get(&krate.id).copied()).unwrap_or(0),
+                "recent_downloads": null,
+                "categories": categories.iter().map(|c| &c.slug).collect::<Vec<_>>(),
+                "keywords": [],
+                "versions": versions.iter().map(|v| v.id).collect::<Vec<_>>(),
+                "max_version": max_version,
+                "max_stable_version": max_stable_version,
+                "newest_version": max_version,
+                "links": {
+                    "owner_team": format!("{api}/owner_team"),
+                    "owner_user": format!("{api}/owner_user"),
+                    "owners": format!("{api}/owners"),
+                    "reverse_dependencies": format!("{api}/reverse_dependencies"),
+                    "version_downloads": format!("{api}/downloads"),
+                    "versions": format!("{api}/versions"),
+                },
+                "created_at": timestamp(&krate.created_at),
+                "updated_at": timestamp(&krate.updated_at),
+                "exact_match": false,
+            },
+            "categories": categories.iter().map(|c| json!({
+                "id": c.slug,
+                "category": c.category,
+                "slug": c.slug,
+                "description": c.description,
+                "crates_cnt": c.crates_cnt,
+                "created_at": timestamp(&c.created_at),
+            })).collect::<Vec<_>>(),
+            "keywords": [],
+            "versions": by_semver.iter().map(|(_, v)| json!({
+                "id": v.id,
+                "crate": name,
+                "num": v.num,
+                "dl_path": format!("{api}/{}/download", v.num),
+                "readme_path": format!("{api}/{}/readme", v.num),
+                "created_at": timestamp(&v.created_at),
+                "updated_at": timestamp(&v.updated_at),
+                "downloads": v.downloads,
+                "features": {},
+                "yanked": v.yanked == "t",
+                "license": v.license,
+                "crate_size": v.crate_size,
+                "rust_version": v.rust_version,
+                "checksum": v.checksum,
+                "published_by": null,
+                "audit_actions": [],
+                "links": {
+                    "authors": format!("{api}/{}/authors", v.num),
+                    "dependencies": format!("{api}/{}/dependencies", v.num),
+                    "version_downloads": format!("{api}/{}/downloads", v.num),
+                },
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl CrateMetadataSource for DbDump {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse> {
+        let krate = self
+            .crates
+            .get(name)
+            .ok_or_else(|| anyhow!("{} isn't in the database dump", name))?;
+        serde_json::from_value(self.response(krate))
+            .with_context(|| format!("could not build the metadata of {} from the dump", name))
+    }
+}
+
+/// PostgreSQL's `2017-11-30 02:20:45.234563` (sometimes with a `+00` offset) as RFC 3339.
+/// The dump's timestamps are all UTC.
+fn timestamp(pg: &str) -> String {
+    let utc = pg.strip_suffix("+00").unwrap_or(pg);
+    format!("{}+00:00", utc.replacen(' ', "T", 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A two-crate dump in the current layout, with download counts in their own table
+    /// and a column this module doesn't read.
+    const FIXTURE: &[(&str, &str)] = &[
+        (
+            "crates.csv",
+            "id,name,created_at,updated_at,description,documentation,homepage,repository,max_upload_size
+1,alpha,2019-01-02 03:04:05.123456,2024-05-06 07:08:09.5+00,A parser,,https://alpha.example.com,https://github.com/example/alpha,
+2,beta,2020-01-01 00:00:00,2020-02-01 00:00:00,,,,,
+",
+        ),
+        (
+            "versions.csv",
+            "id,crate_id,num,created_at,updated_at,downloads,yanked,license,crate_size,rust_version,checksum
+10,1,1.0.0,2019-01-02 03:04:05,2019-01-02 03:04:05,100,f,MIT,1234,,abc
+11,1,1.1.0,2020-01-02 03:04:05,2020-01-02 03:04:05,50,t,MIT,1300,1.60,def
+12,1,2.0.0-rc.1,2021-01-02 03:04:05,2021-01-02 03:04:05,5,f,MIT OR Apache-2.0,1400,1.70,
+20,2,0.1.0,2020-01-01 00:00:00,2020-01-01 00:00:00,1,t,,,,
+21,2,0.2.0,2020-02-01 00:00:00,2020-02-01 00:00:00,2,t,,,,
+",
+        ),
+        (
+            "categories.csv",
+            "id,category,slug,description,crates_cnt,created_at,path
+7,Parsing,parsing,Parsers.,2,2017-01-17 19:13:05.112025,parsing
+",
+        ),
+        ("crates_categories.csv", "crate_id,category_id\n1,7\n"),
+        ("crate_downloads.csv", "crate_id,downloads\n1,155\n2,3\n"),
+    ];
+
+    /// Write the fixture unpacked into `dir/data`, and as `dir/db-dump.tar.gz` with
+    /// everything under `<timestamp>/data/` like the real thing.
+    fn write_fixture(dir: &Path) -> (PathBuf, PathBuf) {
+        let unpacked = dir.join("unpacked");
+        fs::create_dir_all(unpacked.join("data")).unwrap();
+        let tarball = dir.join("db-dump.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&tarball).unwrap(),
+            Compression::fast(),
+        ));
+        for (table, csv) in FIXTURE {
+            fs::write(unpacked.join("data").join(table), csv).unwrap();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(csv.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    format!("2024-06-01-020000/data/{table}"),
+                    csv.as_bytes(),
+                )
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        (unpacked, tarball)
+    }
+
+    #[test]
+    fn reads_a_fixture_dump_like_the_api() {
+        let dir = TempDir::new("dbdump");
+        let (unpacked, _) = write_fixture(dir.path());
+        let dump = DbDump::load(&unpacked).unwrap();
+        assert_eq!(dump.len(), 2);
+
+        let alpha = dump.get_crate("alpha").unwrap();
+        let data = &alpha.crate_data;
+        assert_eq!(data.name, "alpha");
+        assert_eq!(data.description.as_deref(), Some("A parser"));
+        assert_eq!(data.documentation, None);
+        assert_eq!(data.homepage.as_deref(), Some("https://alpha.example.com"));
+        assert_eq!(
+            data.repository.as_deref(),
+            Some("https://github.com/example/alpha")
+        );
+        assert_eq!(data.downloads, 155);
+        // The yanked 1.1.0 is passed over; the pre-release isn't stable.
+        assert_eq!(data.max_version, "2.0.0-rc.1");
+        assert_eq!(data.max_stable_version.as_deref(), Some("1.0.0"));
+        assert_eq!(
+            data.categories.as_deref(),
+            Some(&["parsing".to_owned()][..])
+        );
+        assert_eq!(
+            data.updated_at.to_rfc3339(),
+            "2024-05-06T07:08:09.500+00:00"
+        );
+        let versions: Vec<(&str, bool)> = alpha
+            .versions
+            .iter()
+            .map(|v| (v.num.as_str(), v.yanked))
+            .collect();
+        assert_eq!(
+            versions,
+            [("2.0.0-rc.1", false), ("1.1.0", true), ("1.0.0", false)]
+        );
+        assert_eq!(alpha.categories.len(), 1);
+        assert_eq!(alpha.categories[0].category, "Parsing");
+
+        // Everything yanked: like the API, the newest yanked version.
+        let beta = dump.get_crate("beta").unwrap();
+        assert_eq!(beta.crate_data.max_version, "0.2.0");
+        assert_eq!(beta.crate_data.max_stable_version, None);
+        assert_eq!(beta.crate_data.repository, None);
+        assert!(beta.categories.is_empty());
+
+        assert!(dump.get_crate("gamma").is_err());
+    }
+
+    #[test]
+    fn tarball_and_unpacked_dump_agree() {
+        let dir = TempDir::new("dbdump");
+        let (unpacked, tarball) = write_fixture(dir.path());
+        let from_dir = DbDump::load(&unpacked).unwrap();
+        let from_tarball = DbDump::load(&tarball).unwrap();
+        for name in ["alpha", "beta"] {
+            assert_eq!(
+                serde_json::to_value(from_dir.get_crate(name).unwrap()).unwrap(),
+                serde_json::to_value(from_tarball.get_crate(name).unwrap()).unwrap(),
+                "{name} differs"
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_timestamped_directory_tar_unpacks_into() {
+        let dir = TempDir::new("dbdump");
+        let (unpacked, _) = write_fixture(dir.path());
+        let extracted = dir.path().join("extracted");
+        fs::create_dir(&extracted).unwrap();
+        fs::rename(&unpacked, extracted.join("2024-06-01-020000")).unwrap();
+
+        let dump = DbDump::load(&extracted).unwrap();
+        assert_eq!(dump.get_crate("alpha").unwrap().crate_data.downloads, 155);
+
+        fs::create_dir_all(extracted.join("2024-06-02-020000/data")).unwrap();
+        fs::copy(
+            extracted.join("2024-06-01-020000/data/crates.csv"),
+            extracted.join("2024-06-02-020000/data/crates.csv"),
+        )
+        .unwrap();
+        let err = DbDump::load(&extracted).err().unwrap();
+        assert!(err.to_string().contains("holds 2 dumps"), "{err}");
+    }
+
+    #[test]
+    fn reads_versions_without_download_counts() {
+        let dir = TempDir::new("dbdump");
+        fs::write(
+            dir.path().join("crates.csv"),
+            "id,name,created_at,updated_at,description,documentation,homepage,repository,downloads
+1,alpha,2019-01-02 03:04:05,2019-01-02 03:04:05,,,,,42
+",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("versions.csv"),
+            "id,crate_id,num,created_at,updated_at,yanked,license,crate_size
+10,1,0.1.0,2019-01-02 03:04:05,2019-01-02 03:04:05,f,MIT,100
+",
+        )
+        .unwrap();
+        let alpha = DbDump::load(dir.path())
+            .unwrap()
+            .get_crate("alpha")
+            .unwrap();
+        assert_eq!(alpha.crate_data.downloads, 42);
+        assert_eq!(alpha.crate_data.max_version, "0.1.0");
+        assert_eq!(alpha.versions[0].downloads, 0);
+    }
+
+    #[test]
+    fn rejects_a_dump_without_crates() {
+        let dir = TempDir::new("dbdump");
+        assert!(DbDump::load(dir.path()).is_err());
+    }
+}
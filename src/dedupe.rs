@@ -0,0 +1,111 @@
+//! `--dedupe-dry-run`: how many crates share each repository, and what cloning each
+//! repository only once would save.
+
+use anyhow::Result;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{clone, scan};
+
+#[derive(Default)]
+struct Group {
+    crates: Vec<String>,
+    /// Any checkout of the repository, to measure.
+    checkout: Option<PathBuf>,
+}
+
+/// Group every crate with a repository URL by its normalized URL and print the groups
+/// with more than one crate, most shared first.
+///
+/// The saving of a group is the size of one of its checkouts for every crate past the
+/// first; groups with no checkout under `output` yet can't be measured and are only
+/// counted.
+pub fn print(output: &Path) -> Result<()> {
+    let conn = Connection::open_with_flags("bugbot.sqlite", OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT name, repository, repo_dir FROM crates
+         WHERE repository IS NOT NULL ORDER BY name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Option<String>>(2)?,
+        ))
+    })?;
+    let mut total = 0;
+    for row in rows {
+        let (name, repository, repo_dir) = row?;
+        total += 1;
+        let dir = repo_dir.map_or_else(|| output.join(&name), PathBuf::from);
+        let group = groups.entry(clone::normalize_url(&repository)).or_default();
+        if group.checkout.is_none() && dir.join(".git").exists() {
+            group.checkout = Some(dir);
+        }
+        group.crates.push(name);
+    }
+
+    let unique = groups.len();
+    let mut shared: Vec<(String, Group)> = groups
+        .into_iter()
+        .filter(|(_, group)| group.crates.len() > 1)
+        .collect();
+    shared.sort_by(|(a_url, a), (b_url, b)| {
+        b.crates
+            .len()
+            .cmp(&a.crates.len())
+            .then_with(|| a_url.cmp(b_url))
+    });
+
+    let mut saved = 0;
+    let mut unmeasured = 0;
+    for (url, group) in &shared {
+        let extra = group.crates.len() as u64 - 1;
+        let size = group.checkout.as_deref().map(scan::dir_size);
+        match size {
+            Some(size) => {
+                saved += size * extra;
+                println!(
+                    "{:>6}  {:>10}  {}",
+                    group.crates.len(),
+                    format_size(size * extra),
+                    url
+                );
+            }
+            None => {
+                unmeasured += 1;
+                println!("{:>6}  {:>10}  {}", group.crates.len(), "?", url);
+            }
+        }
+    }
+    println!();
+    println!("{:<28} {:>10}", "crates with a repository", total);
+    println!("{:<28} {:>10}", "unique repositories", unique);
+    println!("{:<28} {:>10}", "shared repositories", shared.len());
+    println!("{:<28} {:>10}", "duplicate clones", total - unique);
+    println!("{:<28} {:>10}", "estimated disk saved", format_size(saved));
+    if unmeasured > 0 {
+        println!(
+            "({} shared repositories have no checkout to measure and aren't counted)",
+            unmeasured
+        );
+    }
+    Ok(())
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,102 @@
+//! Picking checkout directories that stay distinct on case-insensitive filesystems.
+//!
+//! Crate names are case-sensitive, but on macOS and Windows `Foo` and `foo` are the
+//! same directory, so a second crate would land in (or be skipped because of) the
+//! first one's checkout. A crate whose name differs only in case from a directory
+//! that's already there gets `<name>~<n>` instead.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct OutputDirs {
+    root: PathBuf,
+    /// Directory names in `root`, exactly as spelled.
+    exact: HashSet<String>,
+    /// The same names, lowercased.
+    folded: HashSet<String>,
+}
+
+impl OutputDirs {
+    /// Note every directory already in `root`.
+    pub fn scan(root: &Path) -> io::Result<Self> {
+        let mut dirs = OutputDirs {
+            root: root.to_path_buf(),
+            exact: HashSet::new(),
+            folded: HashSet::new(),
+        };
+        for entry in fs::read_dir(root)? {
+            dirs.claim(&entry?.file_name().to_string_lossy());
+        }
+        Ok(dirs)
+    }
+
+    /// The checkout directory for crate `name`, and whether it had to move away from
+    /// `root/<name>` because a differently-cased directory is there.
+    pub fn dir_for(&self, name: &str) -> (PathBuf, bool) {
+        let mut candidate = name.to_owned();
+        let mut n = 0;
+        while !self.exact.contains(&candidate)
+            && self.folded.contains(&candidate.to_ascii_lowercase())
+        {
+            n += 1;
+            candidate = format!("{name}~{n}");
+        }
+        (self.root.join(candidate), n > 0)
+    }
+
+    /// Record that a directory called `dir_name` now exists in `root`.
+    pub fn claim(&mut self, dir_name: &str) {
+        self.folded.insert(dir_name.to_ascii_lowercase());
+        self.exact.insert(dir_name.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn suffixes_names_that_only_differ_in_case() {
+        let mut dirs = OutputDirs::scan(TempDir::new("dirs").path()).unwrap();
+        let root = dirs.root.clone();
+
+        assert_eq!(dirs.dir_for("Foo"), (root.join("Foo"), false));
+        dirs.claim("Foo");
+        // The same crate again gets its own checkout back.
+        assert_eq!(dirs.dir_for("Foo"), (root.join("Foo"), false));
+        assert_eq!(dirs.dir_for("foo"), (root.join("foo~1"), true));
+        dirs.claim("foo~1");
+        assert_eq!(dirs.dir_for("foo"), (root.join("foo~1"), true));
+        assert_eq!(dirs.dir_for("FOO"), (root.join("FOO~2"), true));
+        // Unrelated names are left alone.
+        assert_eq!(dirs.dir_for("foo-bar"), (root.join("foo-bar"), false));
+    }
+
+    #[test]
+    fn scans_what_is_already_in_the_output_directory() {
+        let dir = TempDir::new("dirs");
+        for name in ["Serde", "tokio~1", "rand"] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        let dirs = OutputDirs::scan(dir.path()).unwrap();
+
+        // Checkouts land directly under the output directory, whatever the platform.
+        assert_eq!(dirs.dir_for("rand"), (dir.path().join("rand"), false));
+        assert_eq!(dirs.dir_for("Serde"), (dir.path().join("Serde"), false));
+        assert_eq!(dirs.dir_for("serde"), (dir.path().join("serde~1"), true));
+        assert_eq!(dirs.dir_for("tokio"), (dir.path().join("tokio"), false));
+        assert_eq!(
+            dirs.dir_for("Tokio~1"),
+            (dir.path().join("Tokio~1~1"), true)
+        );
+    }
+
+    #[test]
+    fn needs_the_output_directory_to_exist() {
+        let dir = TempDir::new("dirs");
+        assert!(OutputDirs::scan(&dir.path().join("missing")).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! The `graph` subcommand: dependencies between the crates in the mirror, as DOT or JSON.
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{self, DepKind};
+use crate::status::CrateStatus;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    Dot,
+    Json,
+}
+
+#[derive(Serialize)]
+struct Graph {
+    nodes: Vec<String>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Serialize)]
+struct Edge {
+    from: String,
+    to: String,
+    kind: DepKind,
+}
+
+/// Build the graph from the manifests of every checked-out crate and render it.
+///
+/// Nodes are the crates with a checkout under `output`; an edge is kept only when both
+/// ends are in the mirror, so crates that were never cloned don't show up.
+pub fn render(output: &Path, format: Format) -> Result<String> {
+    let graph = build(output).context("failed to read bugbot.sqlite")?;
+    Ok(match format {
+        Format::Json => serde_json::to_string_pretty(&graph)?,
+        Format::Dot => to_dot(&graph),
+    })
+}
+
+fn build(output: &Path) -> rusqlite::Result<Graph> {
+    let conn = Connection::open_with_flags("bugbot.sqlite", OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let checkouts: Vec<(String, PathBuf)> = conn
+        .prepare(&format!(
+            "SELECT name, repo_dir FROM crates WHERE status IN {} ORDER BY name",
+            CrateStatus::sql_list(&CrateStatus::CHECKED_OUT)
+        ))?
+        .query_map([], |row| {
+            let name: String = row.get(0)?;
+            let dir = row
+                .get::<_, Option<String>>(1)?
+                .map_or_else(|| output.join(&name), PathBuf::from);
+            Ok((name, dir))
+        })?
+        .collect::<Result<_, _>>()?;
+
+    let nodes: HashSet<&str> = checkouts.iter().map(|(name, _)| name.as_str()).collect();
+    let mut edges = Vec::new();
+    for (name, dir) in &checkouts {
+        let Some(manifest_path) = manifest::find_package(dir, name) else {
+            continue;
+        };
+        for (dep, kind) in manifest::dependencies(&manifest_path) {
+            if nodes.contains(dep.as_str()) {
+                edges.push(Edge {
+                    from: name.clone(),
+                    to: dep,
+                    kind,
+                });
+            }
+        }
+    }
+    Ok(Graph {
+        nodes: checkouts.into_iter().map(|(name, _)| name).collect(),
+        edges,
+    })
+}
+
+/// Dev-dependencies are drawn dashed and build-dependencies dotted.
+fn to_dot(graph: &Graph) -> String {
+    let mut dot = String::from("digraph crates {\n");
+    for node in &graph.nodes {
+        let _ = writeln!(dot, "    \"{}\";", node);
+    }
+    for edge in &graph.edges {
+        let style = match edge.kind {
+            DepKind::Normal => "",
+            DepKind::Dev => " [style=dashed]",
+            DepKind::Build => " [style=dotted]",
+        };
+        let _ = writeln!(dot, "    \"{}\" -> \"{}\"{};", edge.from, edge.to, style);
+    }
+    dot.push_str("}\n");
+    dot
+}
@@ -0,0 +1,43 @@
+//! Running the user's post-clone hook.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+/// Run `cmd` through `sh -c` for a freshly cloned crate and append its output to `log`.
+///
+/// The hook gets the crate name and checkout path as `$1` and `$2`, and also as
+/// `BUGBOT_CRATE`/`BUGBOT_PATH` (plus `BUGBOT_REPOSITORY`) in its environment. Returns
+/// the exit code, or `None` if the hook was killed by a signal.
+pub fn run(
+    cmd: &str,
+    name: &str,
+    path: &Path,
+    repository: &str,
+    log: &Path,
+) -> io::Result<Option<i32>> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .arg("bugbot-hook")
+        .arg(name)
+        .arg(path)
+        .env("BUGBOT_CRATE", name)
+        .env("BUGBOT_PATH", path)
+        .env("BUGBOT_REPOSITORY", repository)
+        .output()?;
+
+    let mut log = OpenOptions::new().create(true).append(true).open(log)?;
+    writeln!(
+        log,
+        "=== {} ({}) exited with {}",
+        name,
+        path.display(),
+        output.status
+    )?;
+    log.write_all(&output.stdout)?;
+    log.write_all(&output.stderr)?;
+
+    Ok(output.status.code())
+}
@@ -0,0 +1,53 @@
+//! Spotting repository hosts that can't be reached at all, for `--preflight-hosts`.
+//!
+//! Internal forges behind a VPN make every clone sit through a connection timeout. One
+//! quick request per host tells those apart, and the answer is kept for the rest of the
+//! run so a dead host costs one timeout rather than one per crate.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::debug;
+
+/// How long the probe waits for a host to answer at all.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct HostProbe {
+    client: reqwest::blocking::Client,
+    /// The probe result for each host seen so far: `Err` holds why it's unreachable.
+    seen: HashMap<String, Result<(), String>>,
+}
+
+impl HostProbe {
+    pub fn new() -> reqwest::Result<Self> {
+        let client = crate::tls::http_client().timeout(PROBE_TIMEOUT).build()?;
+        Ok(HostProbe {
+            client,
+            seen: HashMap::new(),
+        })
+    }
+
+    /// Whether the host of `url` answers HTTP at all; any response, even an error
+    /// status, counts. URLs that aren't `http(s)://` are assumed reachable.
+    pub fn check(&mut self, url: &str) -> Result<(), String> {
+        let Some((scheme, rest)) = url.split_once("://") else {
+            return Ok(());
+        };
+        if scheme != "https" && scheme != "http" {
+            return Ok(());
+        }
+        let host = rest.split('/').next().unwrap_or(rest).to_ascii_lowercase();
+        if let Some(result) = self.seen.get(&host) {
+            return result.clone();
+        }
+
+        let result = self
+            .client
+            .head(format!("{}://{}/", scheme, host))
+            .send()
+            .map(|_| ())
+            .map_err(|e| format!("{} is unreachable: {}", host, e));
+        debug!("probed {}: {:?}", host, result);
+        self.seen.insert(host, result.clone());
+        result
+    }
+}
@@ -0,0 +1,55 @@
+//! Working out which crates changed between two commits of the git index.
+
+use anyhow::{Context, Result};
+use crates_index::{Crate, Index};
+use git2::{Delta, DiffOptions, Oid, Repository};
+
+/// The index commit `index` is reading from, resolved the same way crates-index does:
+/// the last fetch if there was one, otherwise HEAD.
+pub fn head_commit(index: &Index) -> Result<Oid> {
+    let repo = Repository::open(index.path()).context("could not open index repository")?;
+    let oid = repo
+        .refname_to_id("FETCH_HEAD")
+        .or_else(|_| repo.refname_to_id("HEAD"))
+        .context("index repository has no usable HEAD")?;
+    Ok(oid)
+}
+
+/// Crates whose index file was added or modified between `since` and `head`.
+///
+/// Fails if `since` is no longer in the repository, which happens when the upstream
+/// index gets squashed; callers should fall back to a full scan then.
+pub fn changed_crates(index: &Index, since: Oid, head: Oid) -> Result<Vec<Crate>> {
+    let repo = Repository::open(index.path()).context("could not open index repository")?;
+    let old = repo
+        .find_commit(since)
+        .context("previous index commit is gone")?
+        .tree()?;
+    let new = repo.find_commit(head)?.tree()?;
+
+    let mut opts = DiffOptions::new();
+    opts.skip_binary_check(true);
+    let diff = repo.diff_tree_to_tree(Some(&old), Some(&new), Some(&mut opts))?;
+
+    let mut crates = Vec::new();
+    for delta in diff.deltas() {
+        if !matches!(delta.status(), Delta::Added | Delta::Modified) {
+            continue;
+        }
+        let Some(path) = delta.new_file().path() else {
+            continue;
+        };
+        // Index files are named after the lowercased crate; top-level files like
+        // config.json have no directory prefix and aren't crates.
+        if path.parent().is_none_or(|p| p.as_os_str().is_empty()) {
+            continue;
+        }
+        if let Some(krate) = path
+            .file_name()
+            .and_then(|n| index.crate_(&n.to_string_lossy()))
+        {
+            crates.push(krate);
+        }
+    }
+    Ok(crates)
+}
@@ -0,0 +1,28 @@
+//! Repositories that keep large files in Git LFS.
+//!
+//! Clones never run the LFS smudge filter, so an LFS repository's checkout holds small
+//! pointer files in place of the real content. `--fetch-lfs` swaps them for the
+//! objects afterwards with `git lfs pull`.
+
+use std::fs;
+use std::path::Path;
+
+use crate::clone::{self, CloneError};
+
+/// Whether the top-level `.gitattributes` of `repo_dir` routes any files through LFS.
+pub fn uses_lfs(repo_dir: &Path) -> bool {
+    fs::read_to_string(repo_dir.join(".gitattributes")).is_ok_and(|attributes| {
+        attributes
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .any(|line| line.split_whitespace().any(|attr| attr == "filter=lfs"))
+    })
+}
+
+/// Download the LFS objects of the checked out commit and check them out. Needs the
+/// `git` CLI with git-lfs installed.
+pub fn pull(repo_dir: &Path) -> Result<(), CloneError> {
+    let mut cmd = clone::git_command();
+    cmd.arg("-C").arg(repo_dir).args(["lfs", "pull"]);
+    clone::run_git(cmd)
+}
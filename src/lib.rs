@@ -0,0 +1,10 @@
+//! Reading a bugbot9000 mirror from other Rust code.
+//!
+//! The `bugbot9000` binary builds the mirror: a directory of checkouts plus the
+//! `bugbot.sqlite` database describing them. This library only reads it back; see
+//! [`mirror::Mirror`]. The statuses a crate can have are in [`status::CrateStatus`].
+
+pub mod mirror;
+pub mod status;
+#[cfg(test)]
+mod testutil;
@@ -0,0 +1,266 @@
+//! Recording a crate's license as an SPDX expression, and `--license-filter`.
+//!
+//! crates.io's `license` field is usually SPDX already, but older crates use the
+//! legacy `MIT/Apache-2.0` form and some have none at all, in which case the license
+//! files in the checkout are recognised instead.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// `expr` with the legacy `/` separator turned into `OR`, operators uppercased and
+/// whitespace tidied. `None` if it's empty.
+pub fn normalize(expr: &str) -> Option<String> {
+    let spaced = expr
+        .replace('/', " OR ")
+        .replace('(', "( ")
+        .replace(')', " )");
+    let tokens: Vec<String> = spaced
+        .split_whitespace()
+        .map(|token| match token.to_ascii_uppercase().as_str() {
+            op @ ("OR" | "AND" | "WITH") => op.to_owned(),
+            _ => token.to_owned(),
+        })
+        .collect();
+    if tokens.is_empty() {
+        return None;
+    }
+    Some(tokens.join(" ").replace("( ", "(").replace(" )", ")"))
+}
+
+/// Phrases that identify a license text, checked in order, so the more specific ones
+/// come first.
+const TEXTS: [(&str, &[&str]); 10] = [
+    ("Apache-2.0", &["Apache License", "Version 2.0"]),
+    ("MPL-2.0", &["Mozilla Public License Version 2.0"]),
+    (
+        "LGPL-3.0",
+        &["GNU LESSER GENERAL PUBLIC LICENSE", "Version 3"],
+    ),
+    ("GPL-3.0", &["GNU GENERAL PUBLIC LICENSE", "Version 3"]),
+    ("GPL-2.0", &["GNU GENERAL PUBLIC LICENSE", "Version 2"]),
+    ("Unlicense", &["This is free and unencumbered software"]),
+    (
+        "BSD-3-Clause",
+        &["Redistribution and use", "Neither the name"],
+    ),
+    ("BSD-2-Clause", &["Redistribution and use"]),
+    (
+        "ISC",
+        &["Permission to use, copy, modify, and/or distribute"],
+    ),
+    ("MIT", &["Permission is hereby granted, free of charge"]),
+];
+
+/// An SPDX expression for the license files (`LICENSE*`, `COPYING*`) directly in
+/// `dir`: the licenses recognised, joined with `OR`, since several license files in
+/// a Rust crate almost always mean a choice. `None` if nothing was recognised.
+pub fn from_files(dir: &Path) -> Option<String> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_ascii_uppercase();
+        if !name.starts_with("LICENSE")
+            && !name.starts_with("LICENCE")
+            && !name.starts_with("COPYING")
+        {
+            continue;
+        }
+        let Ok(text) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some((id, _)) = TEXTS
+            .iter()
+            .find(|(_, phrases)| phrases.iter().all(|p| text.contains(p)))
+        {
+            found.push(*id);
+        }
+    }
+    found.sort_unstable();
+    found.dedup();
+    (!found.is_empty()).then(|| found.join(" OR "))
+}
+
+/// The licenses `--license-filter` accepts.
+pub struct Allowlist(HashSet<String>);
+
+impl Allowlist {
+    /// Parse a comma-separated list of SPDX license identifiers.
+    pub fn parse(list: &str) -> Self {
+        Allowlist(
+            list.split(',')
+                .map(|id| id.trim().to_ascii_lowercase())
+                .filter(|id| !id.is_empty())
+                .collect(),
+        )
+    }
+
+    /// Whether the crate can be used under allowed licenses alone: some side of every
+    /// `OR`, and both sides of every `AND`, must be allowed. `WITH` exceptions only add
+    /// permissions, so they're ignored. An expression that doesn't parse isn't allowed.
+    pub fn allows(&self, expr: &str) -> bool {
+        let tokens: Vec<String> = expr
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect();
+        let mut pos = 0;
+        let allowed = self.or(&tokens, &mut pos);
+        allowed == Some(true) && pos == tokens.len()
+    }
+
+    fn or(&self, tokens: &[String], pos: &mut usize) -> Option<bool> {
+        let mut allowed = self.and(tokens, pos)?;
+        while tokens
+            .get(*pos)
+            .is_some_and(|t| t.eq_ignore_ascii_case("OR"))
+        {
+            *pos += 1;
+            allowed |= self.and(tokens, pos)?;
+        }
+        Some(allowed)
+    }
+
+    fn and(&self, tokens: &[String], pos: &mut usize) -> Option<bool> {
+        let mut allowed = self.term(tokens, pos)?;
+        while tokens
+            .get(*pos)
+            .is_some_and(|t| t.eq_ignore_ascii_case("AND"))
+        {
+            *pos += 1;
+            allowed &= self.term(tokens, pos)?;
+        }
+        Some(allowed)
+    }
+
+    fn term(&self, tokens: &[String], pos: &mut usize) -> Option<bool> {
+        let token = tokens.get(*pos)?;
+        if token == ")"
+            || ["OR", "AND", "WITH"]
+                .iter()
+                .any(|op| token.eq_ignore_ascii_case(op))
+        {
+            return None;
+        }
+        *pos += 1;
+        let allowed = if token == "(" {
+            let allowed = self.or(tokens, pos)?;
+            if tokens.get(*pos).map(String::as_str) != Some(")") {
+                return None;
+            }
+            *pos += 1;
+            allowed
+        } else {
+            // `-or-later` ids and the `+` suffix are the same license, newer versions
+            // allowed.
+            let id = token.trim_end_matches('+').to_ascii_lowercase();
+            let id = id.strip_suffix("-or-later").unwrap_or(&id);
+            let id = id.strip_suffix("-only").unwrap_or(id);
+            self.0.contains(id) || self.0.contains(&token.to_ascii_lowercase())
+        };
+        if tokens
+            .get(*pos)
+            .is_some_and(|t| t.eq_ignore_ascii_case("WITH"))
+        {
+            tokens.get(*pos + 1)?;
+            *pos += 2;
+        }
+        Some(allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn normalizes_the_legacy_slash_form() {
+        assert_eq!(
+            normalize("MIT/Apache-2.0").as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(
+            normalize("  mit or  (Apache-2.0 and\tISC)").as_deref(),
+            Some("mit OR (Apache-2.0 AND ISC)")
+        );
+        assert_eq!(
+            normalize("GPL-2.0 with Classpath-exception-2.0").as_deref(),
+            Some("GPL-2.0 WITH Classpath-exception-2.0")
+        );
+        assert_eq!(normalize("  "), None);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let permissive = Allowlist::parse("MIT, Apache-2.0");
+        assert!(permissive.allows("MIT OR GPL-3.0 AND Zlib"));
+        assert!(!permissive.allows("GPL-3.0 OR Zlib AND MIT"));
+        assert!(permissive.allows("GPL-3.0 AND Zlib OR MIT AND Apache-2.0"));
+        assert!(!permissive.allows("MIT AND GPL-3.0"));
+        assert!(permissive.allows("mit and apache-2.0"));
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let permissive = Allowlist::parse("MIT,Apache-2.0");
+        assert!(!permissive.allows("(MIT OR GPL-3.0) AND Zlib"));
+        assert!(permissive.allows("(MIT OR GPL-3.0) AND (Zlib OR Apache-2.0)"));
+        assert!(permissive.allows("((MIT))"));
+    }
+
+    #[test]
+    fn exceptions_and_later_versions_follow_their_license() {
+        let gpl = Allowlist::parse("GPL-2.0");
+        assert!(gpl.allows("GPL-2.0 WITH Classpath-exception-2.0"));
+        assert!(gpl.allows("GPL-2.0-or-later"));
+        assert!(gpl.allows("GPL-2.0+"));
+        assert!(gpl.allows("GPL-2.0-only"));
+        assert!(!gpl.allows("LGPL-2.0 WITH Classpath-exception-2.0"));
+    }
+
+    #[test]
+    fn legacy_expressions_are_judged_once_normalized() {
+        let permissive = Allowlist::parse("MIT");
+        assert!(permissive.allows(&normalize("MIT/Apache-2.0").unwrap()));
+        assert!(!Allowlist::parse("Zlib").allows(&normalize("MIT/Apache-2.0").unwrap()));
+    }
+
+    #[test]
+    fn malformed_expressions_are_not_allowed() {
+        let permissive = Allowlist::parse("MIT, Apache-2.0");
+        for expr in [
+            "",
+            "MIT OR",
+            "OR MIT",
+            "MIT OR AND",
+            "MIT Apache-2.0",
+            "(MIT",
+            "MIT)",
+            "()",
+            "MIT WITH",
+            "MIT AND (OR Apache-2.0)",
+        ] {
+            assert!(!permissive.allows(expr), "`{expr}` was allowed");
+        }
+    }
+
+    #[test]
+    fn recognises_license_files() {
+        let dir = TempDir::new("license");
+        assert_eq!(from_files(dir.path()), None);
+        fs::write(
+            dir.path().join("LICENSE-MIT"),
+            "Permission is hereby granted, free of charge, to any person",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("LICENSE-APACHE"),
+            "Apache License\n  Version 2.0, January 2004",
+        )
+        .unwrap();
+        fs::write(dir.path().join("README.md"), "Apache License Version 2.0").unwrap();
+        assert_eq!(from_files(dir.path()).as_deref(), Some("Apache-2.0 OR MIT"));
+    }
+}
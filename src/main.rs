@@ -1,130 +1,3219 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use crates_index::Index;
-use crates_io_api::SyncClient;
-use git2::Repository;
-use rusqlite::{params, Connection, OptionalExtension};
-use std::path::PathBuf;
-use std::time::Duration;
+use bugbot9000::status::{self, CrateStatus};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use crates_index::{Crate, Index};
+use crates_io_api::{CrateResponse, SyncClient, User, Version};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use git2::Oid;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use rustsec::Database;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, TryLockError};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn, Level};
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+
+mod advisories;
+mod batch;
+mod benchmark;
+mod clone;
+mod config;
+mod console;
+mod dbdump;
+mod dedupe;
+mod dirs;
+mod graph;
+mod hook;
+mod hosts;
+mod index_diff;
+mod lfs;
+mod license;
+mod manifest;
+mod metadata;
+mod objstore;
+mod overrides;
+mod owner;
+mod preflight;
+#[cfg(unix)]
+mod progress;
+mod reference;
+mod report;
+mod repos;
+mod rules;
+mod scan;
+mod schema;
+mod sidecar;
+mod statsd;
+mod tags;
+mod tarball;
+#[cfg(test)]
+mod testutil;
+mod texts;
+mod tls;
+mod vendored;
+
+use clone::{Auth, CloneError, CloneOptions, Protocol};
+use console::LogFormat;
+use metadata::{CrateMetadataSource, Recorder, Replay, RetryThrottled, Stored};
+use reference::{Change, Reference};
+use repos::{Lookup, SharedRepos};
 
 #[derive(Parser)]
 #[command(version, about = "Clone the latest source repo of every crate on crates.io")]
+#[command(after_help = AFTER_HELP)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Read defaults for any of these options from a TOML file
+    ///
+    /// Keys are option names with underscores, e.g. `delay_ms = 500` or
+    /// `scan_advisories = true`; options given on the command line take precedence.
+    #[arg(long, value_name = "FILE", env = "BUGBOT_CONFIG")]
+    config: Option<PathBuf>,
+
     /// Output directory where repositories will be cloned
-    #[arg(short, long, default_value = "repos")]
+    #[arg(short, long, default_value = "repos", env = "BUGBOT_OUTPUT")]
     output: PathBuf,
 
+    /// Put each cloned crate's checkout, packed as <dir>.tar.gz, in this object store
+    /// and delete it locally: s3://bucket/prefix, gs://..., az://..., or a file:// URL
+    ///
+    /// Clones are still made under --output, so it only needs room for one at a time;
+    /// filtered and name-mismatched checkouts go up too unless they're removed. Checkouts
+    /// adopted by --clone-into-existing and ones whose HEAD fails
+    /// --verify-head-matches-tag stay where they are.
+    /// Credentials come from the store's usual environment variables (AWS_*, GOOGLE_*,
+    /// AZURE_*). archive_url records where each tarball went, and repo_dir is cleared.
+    /// Crates from a repository another crate already used get their own copy, since
+    /// that checkout is gone.
+    #[arg(long, value_name = "URL", env = "BUGBOT_OUTPUT_URL")]
+    output_url: Option<String>,
+
     /// Delay between API requests in milliseconds (default 1100 ms to follow crawler policy)
-    #[arg(short = 'd', long, default_value_t = 1100)]
+    #[arg(short = 'd', long, default_value_t = 1100, env = "BUGBOT_DELAY_MS")]
     delay_ms: u64,
+
+    /// More output: -v adds URLs and timings, -vv adds git transfer progress
+    ///
+    /// BUGBOT_VERBOSE takes the count (0, 1, 2); true and false mean 1 and 0.
+    #[arg(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        value_parser = parse_count,
+        conflicts_with = "quiet",
+        env = "BUGBOT_VERBOSE"
+    )]
+    verbose: u8,
+
+    /// Less output: only errors
+    #[arg(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        value_parser = parse_count,
+        env = "BUGBOT_QUIET"
+    )]
+    quiet: u8,
+
+    /// Log every SQL statement, with its bound parameters, before it runs
+    ///
+    /// Logged at debug level, so it only shows with -v.
+    #[arg(long, env = "BUGBOT_PRINT_SQL")]
+    print_sql: bool,
+
+    /// Format of the line printed for each crate's result
+    ///
+    /// `tsv` and `json` lines go to stdout even with -q, which keeps everything else
+    /// off stdout so they can be piped straight into awk or jq.
+    #[arg(long, value_enum, default_value = "human", env = "BUGBOT_LOG_FORMAT")]
+    log_format: LogFormat,
+
+    /// SSH private key to offer to repositories that ask for credentials
+    #[arg(long, env = "BUGBOT_SSH_KEY")]
+    ssh_key: Option<PathBuf>,
+
+    /// Token to offer to HTTPS repositories that ask for credentials
+    #[arg(long, env = "BUGBOT_GIT_TOKEN", hide_env_values = true)]
+    git_token: Option<String>,
+
+    /// Give up on a repository after this many credential attempts and mark it auth_required
+    #[arg(long, default_value_t = 3, env = "BUGBOT_MAX_AUTH_ATTEMPTS")]
+    max_auth_attempts: u32,
+
+    /// Share one bare object store across all clones via git alternates
+    ///
+    /// Each clone is made with `git clone --reference-if-able <DIR>`, its new objects are
+    /// fetched into the store, and the clone is repacked so only objects missing from the
+    /// store stay local. Requires the `git` CLI.
+    ///
+    /// Caveats: every clone depends on the store, so never move or delete it, and don't
+    /// delete its `refs/bugbot/*` refs or prune it. Repack the store itself only with a
+    /// plain `git gc`/`git repack -a -d`. Inside a clone, use `git repack -a -d -l` or
+    /// `git gc`; a repack without `-l` copies the shared objects back in. Copying a clone
+    /// elsewhere needs `git repack -a -d` first so it no longer uses the store.
+    #[arg(long, value_name = "DIR", env = "BUGBOT_SHARED_OBJECTS")]
+    shared_objects: Option<PathBuf>,
+
+    /// Before the first clone from each HTTP(S) host, check that it answers at all, and
+    /// record crates on hosts that don't as host_unreachable instead of cloning them
+    #[arg(long, alias = "skip-unreachable-hosts", env = "BUGBOT_PREFLIGHT_HOSTS")]
+    preflight_hosts: bool,
+
+    /// PEM file of CA certificates to trust, in addition to the system's, for clones and
+    /// other HTTPS downloads
+    ///
+    /// For internal forges and TLS-intercepting proxies with their own CA. Crates whose
+    /// clone fails certificate verification are recorded as tls_error. The crates.io API
+    /// client only uses the system's certificate store, so a proxy's CA must be installed
+    /// there as well.
+    #[arg(long, value_name = "PATH", env = "BUGBOT_CA_BUNDLE")]
+    ca_bundle: Option<PathBuf>,
+
+    /// Don't verify TLS certificates of repository hosts at all
+    ///
+    /// Last resort for self-signed hosts: anyone on the network path can then serve any
+    /// content under a host's name. Prefer --ca-bundle with the host's certificate.
+    #[arg(long, env = "BUGBOT_INSECURE_TLS")]
+    insecure_tls: bool,
+
+    /// Rewrite GitHub/GitLab/Codeberg/Bitbucket HTTPS URLs to this protocol before cloning,
+    /// falling back to the original URL if that fails
+    #[arg(long, value_enum, env = "BUGBOT_CLONE_PROTOCOL")]
+    clone_protocol: Option<Protocol>,
+
+    /// Set KEY=VALUE in the environment of the git CLI when it clones (repeatable), e.g.
+    /// GIT_ASKPASS, GIT_CONFIG_* or HTTPS_PROXY
+    ///
+    /// Only --shared-objects clones run the git CLI; the default libgit2 clones ignore
+    /// this. BUGBOT_CLONE_ENV takes one per line.
+    #[arg(
+        long,
+        value_name = "KEY=VALUE",
+        env = "BUGBOT_CLONE_ENV",
+        value_delimiter = '\n',
+        value_parser = parse_key_value
+    )]
+    clone_env: Vec<(String, String)>,
+
+    /// TOML file of per-crate clone options: `[[rule]]` entries with a `crate` name
+    /// pattern (`*` and `?` wildcards) and any of protocol, normalize_line_endings,
+    /// shared_objects, ssh_key, max_auth_attempts and env
+    ///
+    /// The first rule whose pattern matches a crate wins; what it leaves out comes from
+    /// the command line.
+    #[arg(long, value_name = "FILE", env = "BUGBOT_CLONE_RULES")]
+    clone_rules: Option<PathBuf>,
+
+    /// Let checkouts convert line endings and apply the repository's text attributes, as
+    /// a plain `git clone` would
+    ///
+    /// By default a checkout holds the exact bytes stored in the repository, so hashes
+    /// and diffs against published tarballs aren't thrown off by CRLF conversion.
+    #[arg(long, env = "BUGBOT_NORMALIZE_LINE_ENDINGS")]
+    normalize_line_endings: bool,
+
+    /// After cloning, check out the git tag of the crate's newest published version
+    #[arg(long, env = "BUGBOT_CHECKOUT_VERSION_TAG")]
+    checkout_version_tag: bool,
+
+    /// Let yanked versions be picked by --checkout-version-tag (e.g. for forensics on a
+    /// version yanked over a vulnerability)
+    #[arg(long, env = "BUGBOT_INCLUDE_YANKED_VERSIONS")]
+    include_yanked_versions: bool,
+
+    /// Check out the newest version that has a git tag, walking back from the newest
+    /// published one when it wasn't tagged; implies --checkout-version-tag
+    #[arg(long, env = "BUGBOT_ONLY_NEWEST_VERSION_WITH_TAG")]
+    only_newest_version_with_tag: bool,
+
+    /// After --checkout-version-tag, make sure HEAD is the tag's commit and record the
+    /// crate as head_tag_mismatch if it isn't; does nothing when no tag was found
+    #[arg(long, env = "BUGBOT_VERIFY_HEAD_MATCHES_TAG")]
+    verify_head_matches_tag: bool,
+
+    /// Leave vendored C/C++ directories next to a build.rs out of the checkout, using a
+    /// git sparse checkout (requires the `git` CLI)
+    ///
+    /// Whether anything was left out is stored in the `vendored_excluded` column;
+    /// `git sparse-checkout disable` in the clone brings the files back.
+    #[arg(long, env = "BUGBOT_SKIP_VENDORED")]
+    skip_vendored: bool,
+
+    /// Record the commit each published version was tagged at in the versions table
+    #[arg(long, env = "BUGBOT_TAGS_AS_VERSIONS")]
+    tags_as_versions: bool,
+
+    /// Download Git LFS objects after cloning a repository that uses LFS (requires the
+    /// `git` CLI with git-lfs)
+    ///
+    /// Without it, such checkouts keep LFS pointer files. Either way the `lfs` column
+    /// records `fetched` or `pointers_only` for them.
+    #[arg(long, env = "BUGBOT_FETCH_LFS")]
+    fetch_lfs: bool,
+
+    /// Also download each crate's newest published .crate file from static.crates.io
+    /// into the output directory, verified against the index checksum
+    ///
+    /// The version downloaded is stored in the `tarball_version` column.
+    #[arg(long, env = "BUGBOT_FETCH_TARBALL")]
+    fetch_tarball: bool,
+
+    /// Download the .crate file instead of cloning (status tarball_ok); implies
+    /// --fetch-tarball
+    #[arg(long, env = "BUGBOT_TARBALL_ONLY")]
+    tarball_only: bool,
+
+    /// Compare each downloaded .crate file with the commit it was published from and
+    /// record files that are missing from git or differ in the `discrepancies` table;
+    /// implies --fetch-tarball
+    #[arg(long, env = "BUGBOT_COMPARE_TARBALL_TO_GIT")]
+    compare_tarball_to_git: bool,
+
+    /// Delete clones of empty repositories (status empty_repo) so the next run tries again
+    #[arg(long, env = "BUGBOT_REMOVE_EMPTY_REPOS")]
+    remove_empty_repos: bool,
+
+    /// Record checkouts already in the output directory that the database doesn't know
+    /// about, instead of skipping them, and clone only what's missing
+    ///
+    /// For taking over a mirror made by another tool. Each such directory must be a git
+    /// repository whose origin is the crate's repository URL; it is then recorded as
+    /// cloned and handled like a fresh clone, except that it is never deleted (by
+    /// --remove-empty-repos or --remove-filtered) and isn't fetched. A directory that
+    /// fails the check is recorded as failed and left alone. Checkouts of crates already
+    /// in the database are treated as before: done ones are skipped, and interrupted ones
+    /// are resumed.
+    #[arg(long, env = "BUGBOT_CLONE_INTO_EXISTING")]
+    clone_into_existing: bool,
+
+    /// Record crates whose manifest targets an older edition than this (e.g. 2021) as
+    /// edition_filtered
+    ///
+    /// The edition and rust-version can only be read from the manifest, so the filter
+    /// runs after the clone: filtered crates still cost a clone, and their checkout is
+    /// kept unless --remove-filtered is given. A manifest without `edition` is 2015.
+    /// Filtered crates aren't checked again on later runs.
+    #[arg(long, value_name = "YEAR", env = "BUGBOT_MIN_EDITION")]
+    min_edition: Option<u16>,
+
+    /// Record crates whose manifest declares an older rust-version than this (e.g. 1.70),
+    /// or none at all, as edition_filtered; runs after the clone, like --min-edition
+    #[arg(long, value_name = "VERSION", value_parser = parse_min_rust_version, env = "BUGBOT_MIN_RUST_VERSION")]
+    min_rust_version: Option<(u64, u64, u64)>,
+
+    /// Delete the checkout of crates filtered out by --min-edition, --min-rust-version or
+    /// --require-tests
+    #[arg(long, env = "BUGBOT_REMOVE_FILTERED")]
+    remove_filtered: bool,
+
+    /// Record a crate whose clone or post-clone steps panic as panicked and carry on,
+    /// instead of ending the run
+    #[arg(long, env = "BUGBOT_KEEP_GOING_ON_PANIC")]
+    keep_going_on_panic: bool,
+
+    /// Write a bugbot.json into each new checkout with the crate's name, newest
+    /// version, repository URL, HEAD commit and clone time
+    #[arg(long, env = "BUGBOT_OUTPUT_MANIFEST_PER_CRATE")]
+    output_manifest_per_crate: bool,
+
+    /// Record at most N crates against one repository; later ones get status repo_cap
+    ///
+    /// A repository is cloned once, for the first crate that names it. Every other crate
+    /// with the same (normalized) URL is looked up in that checkout without any network
+    /// call and recorded as shared_repo, with repo_dir pointing at the checkout. With
+    /// --checkout-version-tag the checkout stays at the first crate's tag.
+    #[arg(long, value_name = "N", env = "BUGBOT_MAX_CRATES_PER_REPO")]
+    max_crates_per_repo: Option<u64>,
+
+    /// Also fetch each crate's owners into the owners table (one extra API call per crate)
+    #[arg(long, env = "BUGBOT_FETCH_OWNERS")]
+    fetch_owners: bool,
+
+    /// Checkpoint the SQLite write-ahead log every N processed crates when
+    /// --checkpoint-every is 0 (0 disables)
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1000,
+        env = "BUGBOT_CHECKPOINT_INTERVAL"
+    )]
+    checkpoint_interval: u64,
+
+    /// Commit database writes once every N processed crates, checkpointing the WAL after
+    /// each commit instead of every --checkpoint-interval crates (0 commits every write
+    /// on its own)
+    ///
+    /// Fewer commits mean fewer fsyncs, but a crash loses up to N crates of progress,
+    /// which the next run then processes again, taking over any checkouts they left
+    /// behind. Other readers of the database only see a crate once its batch is
+    /// committed. Recording crates in batches of 100 is about ten times faster than
+    /// committing every write, and larger batches gain little more.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        env = "BUGBOT_CHECKPOINT_EVERY"
+    )]
+    checkpoint_every: u64,
+
+    /// Fetch and store crate metadata only; never clone (statuses end up metadata_ok)
+    ///
+    /// Separates the rate-limited metadata harvest from the bandwidth-heavy clone phase.
+    /// Crates already at metadata_ok are skipped, so an interrupted pass picks up where it
+    /// stopped; a later run without this flag clones them.
+    #[arg(long, env = "BUGBOT_STATE_ONLY")]
+    state_only: bool,
+
+    /// Save every crate metadata response as DIR/<crate>.json
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "replay",
+        env = "BUGBOT_RECORD"
+    )]
+    record: Option<PathBuf>,
+
+    /// Read crate metadata from responses saved by --record instead of the API
+    ///
+    /// Makes runs repeatable and skips the rate limit. Only the per-crate metadata call is
+    /// replayed; --fetch-owners and --fetch-dependents still go to the API.
+    #[arg(long, value_name = "DIR", env = "BUGBOT_REPLAY")]
+    replay: Option<PathBuf>,
+
+    /// Read crate metadata from a crates.io database dump (the db-dump.tar.gz, or the
+    /// directory it was unpacked into) instead of the API
+    ///
+    /// Repositories, downloads, versions and categories all come from the dump, so the
+    /// metadata phase makes no requests; crate names still come from the index, and
+    /// --fetch-owners and --fetch-dependents still go to the API.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["replay", "record"],
+        env = "BUGBOT_FROM_DB_DUMP"
+    )]
+    from_db_dump: Option<PathBuf>,
+
+    /// Zlib-compress long descriptions and error messages in the crate_texts table
+    #[arg(long, env = "BUGBOT_COMPRESS_DB")]
+    compress_db: bool,
+
+    /// Leave crates without a repository URL out of the database instead of recording
+    /// them as no_repo
+    #[arg(long, env = "BUGBOT_SKIP_NO_REPO_ROWS")]
+    skip_no_repo_rows: bool,
+
+    /// Run VACUUM on the database at the end of the run to reclaim space
+    #[arg(long, env = "BUGBOT_VACUUM")]
+    vacuum: bool,
+
+    /// Write a gzip-compressed copy of the database to FILE at the end of the run
+    #[arg(long, value_name = "FILE", env = "BUGBOT_BACKUP")]
+    backup: Option<PathBuf>,
+
+    /// Only process crates whose index entry changed since the last completed run
+    ///
+    /// Falls back to a full scan when no previous run is recorded or its index commit is
+    /// no longer available (e.g. after the upstream index was squashed).
+    #[arg(long, env = "BUGBOT_ONLY_CHANGED")]
+    only_changed: bool,
+
+    /// Start on the locally cached index while it updates in the background, then
+    /// process whatever crates the update added or changed in a second pass
+    ///
+    /// Saves waiting for the index fetch up front. Crates the first pass already handled
+    /// aren't revisited for what the update changed about them.
+    #[arg(long, env = "BUGBOT_BACKGROUND_INDEX_UPDATE")]
+    background_index_update: bool,
+
+    /// Only process crates that are new, or have a new version, compared with another
+    /// bugbot database (e.g. last quarter's snapshot)
+    ///
+    /// Each selected crate is logged as new or changed, and crates whose repository URL
+    /// differs from the one recorded there are reported once their metadata is fetched.
+    #[arg(long, value_name = "DB", env = "BUGBOT_DIFF_AGAINST")]
+    diff_against: Option<PathBuf>,
+
+    /// Only process crates whose name sorts at or after NAME (case-insensitively)
+    ///
+    /// With --until-crate this splits the index into ranges that can run on different
+    /// machines, e.g. `--until-crate n` on one and `--since-crate n` on another.
+    #[arg(long, value_name = "NAME", env = "BUGBOT_SINCE_CRATE")]
+    since_crate: Option<String>,
+
+    /// Only process crates whose name sorts before NAME (case-insensitively)
+    #[arg(long, value_name = "NAME", env = "BUGBOT_UNTIL_CRATE")]
+    until_crate: Option<String>,
+
+    /// Only process the crates in shard I of N (counting from 0), picked by a stable hash
+    /// of the crate name
+    ///
+    /// Running `--shard 0/3`, `1/3` and `2/3` on three machines covers every crate
+    /// exactly once without any coordination. Give each shard its own working directory,
+    /// and so its own bugbot.sqlite and lock; merge the databases afterwards if needed.
+    #[arg(long, value_name = "I/N", value_parser = parse_shard, env = "BUGBOT_SHARD")]
+    shard: Option<Shard>,
+
+    /// Only process crates owned by this crates.io user
+    ///
+    /// Their crates are listed through the API (one request per 100 crates) and looked
+    /// up in the index directly, so the rest of the index is never walked.
+    #[arg(long, value_name = "LOGIN", env = "BUGBOT_OWNER")]
+    owner: Option<String>,
+
+    /// Only process the crates named in this file, one per line (blank lines and lines
+    /// starting with `#` are ignored), such as one written by --dump-failed
+    ///
+    /// With --owner, only the listed crates that user owns are processed.
+    #[arg(long, value_name = "FILE", env = "BUGBOT_CRATES_FILE")]
+    crates_file: Option<PathBuf>,
+
+    /// Clone these repositories instead of the ones crates.io lists, for crates whose
+    /// metadata is wrong or missing
+    ///
+    /// Either TOML (`crate-name = "https://..."`) or, for a `.csv` file, `name,url` lines.
+    /// Crates cloned from an override have overridden = 1.
+    #[arg(long, value_name = "FILE", env = "BUGBOT_REPO_URL_OVERRIDES")]
+    repo_url_overrides: Option<PathBuf>,
+
+    /// Only clone crates that can be used under these licenses alone (comma-separated
+    /// SPDX identifiers, e.g. MIT,Apache-2.0,BSD-3-Clause); others get license_filtered
+    ///
+    /// Checked against the newest version's license on crates.io before cloning:
+    /// `MIT OR GPL-3.0` passes with MIT allowed, `MIT AND GPL-3.0` needs both. Crates
+    /// with no license there are filtered too. Every crate's license is stored in the
+    /// `license` column, read from the checkout's license files when crates.io has none.
+    #[arg(long, value_name = "IDS", env = "BUGBOT_LICENSE_FILTER")]
+    license_filter: Option<String>,
+
+    /// Shell command to run after each successful clone
+    ///
+    /// Runs via `sh -c` with the crate name and checkout path as `$1` and `$2`, also
+    /// exported as BUGBOT_CRATE, BUGBOT_PATH and BUGBOT_REPOSITORY. The exit code is stored
+    /// in the `hook_status` column.
+    #[arg(long, value_name = "CMD", env = "BUGBOT_POST_CLONE_HOOK")]
+    post_clone_hook: Option<String>,
+
+    /// File the post-clone hook's output is appended to
+    #[arg(
+        long,
+        value_name = "FILE",
+        default_value = "hooks.log",
+        env = "BUGBOT_HOOK_LOG"
+    )]
+    hook_log: PathBuf,
+
+    /// Check each clone's Cargo.lock against the RustSec advisory database
+    #[arg(long, env = "BUGBOT_SCAN_ADVISORIES")]
+    scan_advisories: bool,
+
+    /// Where the RustSec advisory database is cloned and kept up to date
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "advisory-db",
+        env = "BUGBOT_ADVISORY_DB"
+    )]
+    advisory_db: PathBuf,
+
+    /// Count unsafe blocks, functions, impls and traits in each clone (parses every .rs file)
+    #[arg(long, env = "BUGBOT_SCAN_UNSAFE")]
+    scan_unsafe: bool,
+
+    /// Record in `has_tests` whether each clone has a tests/ directory or #[test]
+    /// functions (reads every .rs file)
+    #[arg(long, env = "BUGBOT_SCAN_TESTS")]
+    scan_tests: bool,
+
+    /// Record crates without tests as tests_filtered; implies --scan-tests
+    ///
+    /// Runs after the clone, like --min-edition, and --remove-filtered deletes their
+    /// checkout too.
+    #[arg(long, env = "BUGBOT_REQUIRE_TESTS")]
+    require_tests: bool,
+
+    /// Fetch and store each crate's reverse dependency count (one extra API call per crate)
+    #[arg(long, env = "BUGBOT_FETCH_DEPENDENTS")]
+    fetch_dependents: bool,
+
+    /// Skip crates with fewer than N reverse dependencies; implies --fetch-dependents
+    #[arg(long, value_name = "N", env = "BUGBOT_MIN_DEPENDENTS")]
+    min_dependents: Option<u64>,
+
+    /// Reuse a stored reverse dependency count for this many days before fetching it again
+    #[arg(
+        long,
+        value_name = "DAYS",
+        default_value_t = 7,
+        env = "BUGBOT_DEPENDENTS_MAX_AGE_DAYS"
+    )]
+    dependents_max_age_days: u64,
+
+    /// Make at most N crates.io metadata requests, then stop as if --max-runtime ran out
+    ///
+    /// Responses served by --replay or --from-db-dump don't count. --fetch-owners and --fetch-dependents
+    /// make requests of their own on top of these.
+    #[arg(long, value_name = "N", env = "BUGBOT_MAX_API_CALLS")]
+    max_api_calls: Option<u64>,
+
+    /// Re-fetch the crates.io metadata of crates that are otherwise done once it's older
+    /// than this (e.g. 7d), so downloads and versions stay current
+    ///
+    /// Only the metadata columns, description and categories are refreshed; the crate
+    /// isn't cloned again. Refetches count towards --max-api-calls.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BUGBOT_REFETCH_METADATA_AFTER")]
+    refetch_metadata_after: Option<Duration>,
+
+    /// Check whether crates.io lists a new repository URL for crates already cloned, and
+    /// if so point the checkout's origin there, fetch, and record repo_moved
+    ///
+    /// Costs one metadata request per cloned crate per run, or only for crates whose
+    /// metadata is stale when combined with --refetch-metadata-after. Crates with a
+    /// --repo-url-overrides URL are left alone.
+    #[arg(long, env = "BUGBOT_DETECT_MOVED_REPOS")]
+    detect_moved_repos: bool,
+
+    /// Stop starting new crates after this long (e.g. 90m, 12h, 2d)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BUGBOT_MAX_RUNTIME")]
+    max_runtime: Option<Duration>,
+
+    /// Pause between crates for as long as FILE exists
+    ///
+    /// Lets an operator free up bandwidth or disk for a while without stopping the run.
+    /// Ctrl-C and --max-runtime still end a paused run.
+    #[arg(long, value_name = "FILE", env = "BUGBOT_PAUSE_FILE")]
+    pause_file: Option<PathBuf>,
+
+    /// When the output disk is (nearly) full, wait for space to be freed instead of
+    /// failing every clone from then on
+    ///
+    /// Free space is checked before each clone, and a clone that fails with "No space
+    /// left on device" is retried once there is room again.
+    #[arg(long, env = "BUGBOT_WAIT_ON_DISK_FULL")]
+    wait_on_disk_full: bool,
+
+    /// Free space below which --wait-on-disk-full waits, in MiB
+    #[arg(
+        long,
+        value_name = "MIB",
+        default_value_t = 1024,
+        env = "BUGBOT_MIN_FREE_MB"
+    )]
+    min_free_mb: u64,
+
+    /// Publish a JSON event for each finished crate on a Unix socket at PATH
+    ///
+    /// Events look like the --log-format json lines. Clients that connect mid-run get
+    /// events from then on; ones that don't keep up miss events rather than slowing the
+    /// run down. Only available on Unix.
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = parse_socket_path,
+        env = "BUGBOT_PROGRESS_SOCKET"
+    )]
+    progress_socket: Option<PathBuf>,
+
+    /// Push metrics to the StatsD server at ADDR (host:port) over UDP as crates finish
+    ///
+    /// Counters `crates.processed` and `crates.<status>` for every crate, and for each
+    /// clone the timer `clone.duration` and the counter `clone.bytes`, all under
+    /// --statsd-prefix.
+    #[arg(long, value_name = "ADDR", env = "BUGBOT_STATSD")]
+    statsd: Option<String>,
+
+    /// Prefix for the names of --statsd metrics
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = "bugbot",
+        env = "BUGBOT_STATSD_PREFIX"
+    )]
+    statsd_prefix: String,
+
+    /// Run even if another instance holds bugbot.lock
+    #[arg(long, env = "BUGBOT_FORCE")]
+    force: bool,
+
+    /// Print how many crates are in each status and exit, without touching the index,
+    /// the network or the lock
+    #[arg(long, env = "BUGBOT_ONLY_STATUS")]
+    only_status: bool,
+
+    /// Write the names of crates that failed to FILE, one per line, and exit; feed it
+    /// back with --crates-file to retry just those
+    ///
+    /// Like --only-status, this only reads bugbot.sqlite.
+    #[arg(long, value_name = "FILE", env = "BUGBOT_DUMP_FAILED")]
+    dump_failed: Option<PathBuf>,
+
+    /// Which statuses --dump-failed writes (repeatable, or comma-separated)
+    #[arg(
+        long,
+        value_name = "STATUS",
+        env = "BUGBOT_DUMP_STATUS",
+        value_delimiter = ',',
+        value_parser = parse_status,
+        default_values_t = CrateStatus::FAILURES,
+        requires = "dump_failed"
+    )]
+    dump_status: Vec<CrateStatus>,
+
+    /// Print how many crates share each repository URL (after normalization), most
+    /// shared first, and how much disk cloning each repository once would save, then exit
+    ///
+    /// Like --only-status, this only reads bugbot.sqlite and the checkouts it lists.
+    #[arg(long, env = "BUGBOT_DEDUPE_DRY_RUN")]
+    dedupe_dry_run: bool,
+
+    /// Print every status a crate can be recorded with, and what it means, then exit
+    #[arg(long, env = "BUGBOT_LIST_STATUSES")]
+    list_statuses: bool,
+
+    /// Check that crates.io and a git host are reachable, then exit
+    #[arg(long, env = "BUGBOT_PROBE_ONLY")]
+    probe_only: bool,
+
+    /// Run the --probe-only checks first and abort the run if they fail
+    #[arg(long, env = "BUGBOT_PREFLIGHT")]
+    preflight: bool,
+
+    /// Repository whose refs the network checks list
+    #[arg(long, value_name = "URL", default_value = preflight::DEFAULT_PROBE_REPO, env = "BUGBOT_PROBE_REPO")]
+    probe_repo: String,
+
+    /// Exit with 0 even if crates failed or the run was cut short
+    #[arg(long, env = "BUGBOT_ALWAYS_EXIT_ZERO")]
+    always_exit_zero: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the JSON schema of the --config file
+    #[cfg(feature = "schema")]
+    #[command(hide = true)]
+    PrintConfigSchema,
+
+    /// Phase one of a two-phase run: fetch and store the metadata of every crate without
+    /// cloning, like --state-only, keeping each crates.io response in the database
+    ///
+    /// Metadata comes from wherever a normal run would get it (the API, --replay or
+    /// --from-db-dump), and an interrupted harvest picks up where it stopped.
+    Harvest,
+
+    /// Phase two: clone the crates `harvest` stored, with their metadata read back from
+    /// the database instead of crates.io
+    ///
+    /// Crates that weren't harvested are left alone. Since no metadata requests are
+    /// made, this can be run as often as needed with different filters.
+    Clone,
+
+    /// Write a self-contained HTML summary of bugbot.sqlite: statuses, forges, the
+    /// largest checkouts, failure reasons and a searchable list of crates
+    Report {
+        /// File to write the report to
+        #[arg(long, value_name = "FILE", default_value = "report.html")]
+        output: PathBuf,
+    },
+
+    /// Print the dependency graph between the checked-out crates, read from their
+    /// Cargo.toml files
+    Graph {
+        #[arg(long, value_enum, default_value = "dot")]
+        format: graph::Format,
+
+        /// Write the graph to FILE instead of stdout
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Time metadata fetching, cloning and database writes on a fixed sample of crates,
+    /// with the clone options given, and print throughput per phase
+    ///
+    /// Nothing is kept: the clones go to a scratch directory that is deleted afterwards,
+    /// and the database is in memory.
+    Benchmark {
+        /// Crates to use instead of the built-in sample
+        #[arg(long, value_name = "NAME", value_delimiter = ',')]
+        crates: Vec<String>,
+
+        /// Scratch directory for the clones
+        #[arg(long, value_name = "DIR", default_value = "bugbot-benchmark")]
+        dir: PathBuf,
+    },
 }
 
-fn main() -> Result<()> {
+const AFTER_HELP: &str = "\
+Every option can also be set with the environment variable shown next to it (switches
+take true or false), or in the --config file (BUGBOT_CONFIG). A flag on the command line
+beats the environment variable, which beats the config file, which beats the built-in
+default.
+
+Exit codes:
+  0  every crate attempted was cloned (or had nothing to clone)
+  1  the run couldn't start (database, index or API client setup failed)
+  2  invalid command-line arguments
+  3  at least one crate failed to clone or fetch metadata
+  4  the run was cut short by --max-runtime, --max-api-calls or Ctrl-C
+  5  crates.io blocked our requests (HTTP 403); see the message for what to do
+
+With --always-exit-zero, 3 and 4 are reported as 0.";
+
+/// Sent with every HTTP request we make, API or otherwise.
+const USER_AGENT: &str = "crates_mirror/0.1.0 (https://github.com/yourname/crates_mirror)";
+
+/// Locked for the duration of a run so that two instances never share the database
+/// and output directory.
+const LOCK_FILE: &str = "bugbot.lock";
+
+/// The run finished, but some crates failed.
+const EXIT_FAILURES: u8 = 3;
+/// The run stopped early because of `--max-runtime`, `--max-api-calls` or Ctrl-C.
+const EXIT_INTERRUPTED: u8 = 4;
+/// crates.io answered a metadata request with 403, i.e. it has blocked us.
+const EXIT_BANNED: u8 = 5;
+
+fn main() -> Result<ExitCode> {
+    let mut args = parse_args()?;
+    let harvest = matches!(args.command, Some(Command::Harvest));
+    let clone_phase = matches!(args.command, Some(Command::Clone));
+    if harvest {
+        args.state_only = true;
+    }
+    let args = args;
+    if let Some(command) = &args.command {
+        match command {
+            Command::Harvest | Command::Clone => {}
+            #[cfg(feature = "schema")]
+            Command::PrintConfigSchema => {
+                println!("{}", config::schema());
+                return Ok(ExitCode::SUCCESS);
+            }
+            Command::Report { output } => {
+                report::write(&args.output, output)?;
+                println!("Wrote report to {}", output.display());
+                return Ok(ExitCode::SUCCESS);
+            }
+            Command::Graph { format, output } => {
+                let graph = graph::render(&args.output, *format)?;
+                match output {
+                    Some(path) => std::fs::write(path, graph)
+                        .with_context(|| format!("could not write {}", path.display()))?,
+                    None => print!("{}", graph),
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Command::Benchmark { crates, dir } => {
+                init_logging(&args);
+                init_tls(&args)?;
+                let crates = if crates.is_empty() {
+                    benchmark::SAMPLE.map(String::from).to_vec()
+                } else {
+                    crates.clone()
+                };
+                benchmark::run(&args, &crates, dir)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+        }
+    }
+    init_logging(&args);
+    init_tls(&args)?;
+
+    if args.list_statuses {
+        for status in CrateStatus::ALL {
+            println!("{:<20} {}", status.as_str(), status.description());
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.only_status {
+        print_status().context("failed to read bugbot.sqlite")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(path) = &args.dump_failed {
+        let count = dump_crates(path, &args.dump_status).context("failed to read bugbot.sqlite")?;
+        info!("Wrote {} crate names to {}", count, path.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.dedupe_dry_run {
+        dedupe::print(&args.output).context("failed to read bugbot.sqlite")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // ─── crates.io API client ────────────────────────────────────────────────────
+    let client = SyncClient::new(USER_AGENT, Duration::from_millis(args.delay_ms))
+        .context("could not create crates.io API client")?;
+
+    if args.probe_only || args.preflight {
+        preflight::check(&client, &args.probe_repo).context("network check failed")?;
+        if args.probe_only {
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+
+    // The first Ctrl-C lets the current crate finish; a second one exits immediately.
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        ctrlc::set_handler(move || {
+            if stop.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            warn!("Interrupted; stopping after the current crate (Ctrl-C again to abort)");
+        })
+        .context("failed to install Ctrl-C handler")?;
+    }
+    // A deadline too far off to represent is no deadline at all.
+    let deadline = args.max_runtime.and_then(|d| Instant::now().checked_add(d));
+
+    // ─── Instance lock ───────────────────────────────────────────────────────────
+    // Held for the rest of the run; the OS releases it when the process exits, however
+    // that happens, so a crashed run never leaves a stale lock behind.
+    let lock = File::create(LOCK_FILE).context("failed to create bugbot.lock")?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if args.force => {
+            warn!("Another instance holds bugbot.lock; continuing because of --force");
+        }
+        Err(TryLockError::WouldBlock) => {
+            anyhow::bail!(
+                "another instance is already running here (bugbot.lock is held); pass --force to run anyway"
+            );
+        }
+        Err(TryLockError::Error(e)) => return Err(e).context("failed to lock bugbot.lock"),
+    }
+
     // ─── Database setup ──────────────────────────────────────────────────────────
-    let conn = Connection::open("bugbot.sqlite").context("failed to open bugbot.sqlite")?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS crates (
-            name        TEXT PRIMARY KEY,
-            repository  TEXT,
-            status      TEXT NOT NULL
-        )",
-        [],
-    )
-    .context("failed to create crates table")?;
+    let mut conn = Connection::open("bugbot.sqlite").context("failed to open bugbot.sqlite")?;
+    if args.print_sql {
+        conn.trace(Some(print_sql));
+    }
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+        .context("failed to enable WAL mode")?;
+    schema::migrate(&conn)?;
 
-    // ─── CLI + filesystem prep ───────────────────────────────────────────────────
-    let args = Args::parse();
+    // ─── Filesystem prep ─────────────────────────────────────────────────────────
     std::fs::create_dir_all(&args.output).context("failed to create output directory")?;
+    let mut output_dirs =
+        dirs::OutputDirs::scan(&args.output).context("failed to list output directory")?;
 
     // ─── Fetch crates index ──────────────────────────────────────────────────────
     let mut index = Index::new_cargo_default().context("could not open crates.io index")?;
-    index.update().context("could not update crates.io index")?;
-    let crates: Vec<_> = index.crates().collect();
-    println!("Found {} crates in the index", crates.len());
+    if !args.background_index_update {
+        index.update().context("could not update crates.io index")?;
+    }
+    // Read before a background update starts moving FETCH_HEAD.
+    let mut index_head = index_diff::head_commit(&index)?;
+    let mut background_update = args
+        .background_index_update
+        .then(|| std::thread::spawn(update_index));
 
-    // ─── crates.io API client ────────────────────────────────────────────────────
-    let client = SyncClient::new(
-        "crates_mirror/0.1.0 (https://github.com/yourname/crates_mirror)",
-        Duration::from_millis(args.delay_ms),
-    )
-    .context("could not create crates.io API client")?;
+    let mut selected = match &args.owner {
+        Some(login) => {
+            let owned = owner::owned_crates(&client, login)?;
+            info!("{} owns {} crates", login, owned.len());
+            Some(owned)
+        }
+        None => None,
+    };
+    if let Some(path) = &args.crates_file {
+        let listed =
+            read_crate_list(path).with_context(|| format!("could not read {}", path.display()))?;
+        info!("{} lists {} crates", path.display(), listed.len());
+        selected = Some(match selected {
+            Some(owned) => owned
+                .into_iter()
+                .filter(|name| listed.contains(name))
+                .collect(),
+            None => listed,
+        });
+    }
+
+    let since = if args.only_changed {
+        get_meta(&conn, LAST_INDEX_COMMIT)?
+    } else {
+        None
+    };
+    let crates: Vec<_> = match since {
+        Some(since) => {
+            let changed = Oid::from_str(&since)
+                .map_err(anyhow::Error::from)
+                .and_then(|since| index_diff::changed_crates(&index, since, index_head));
+            match changed {
+                Ok(mut crates) => {
+                    if let Some(selected) = &selected {
+                        crates.retain(|krate| selected.iter().any(|name| name == krate.name()));
+                    }
+                    info!(
+                        "{} crates changed since index commit {}",
+                        crates.len(),
+                        since
+                    );
+                    crates
+                }
+                Err(e) => {
+                    warn!(
+                        "can't diff against index commit {}: {:#}; doing a full scan",
+                        since, e
+                    );
+                    all_crates(&index, selected.as_deref())
+                }
+            }
+        }
+        None => {
+            if args.only_changed {
+                info!("No previous index commit recorded; doing a full scan");
+            }
+            all_crates(&index, selected.as_deref())
+        }
+    };
+    info!("Found {} crates in the index", crates.len());
+
+    let reference = match &args.diff_against {
+        Some(path) => Some(Reference::load(path)?),
+        None => None,
+    };
+    let crates = narrow_crates(&args, reference.as_ref(), crates);
+
+    // With the index updating in the background, crates it adds are processed once
+    // everything from the cached index is done.
+    let mut first_pass: HashSet<String> = HashSet::new();
+    if background_update.is_some() {
+        first_pass.extend(crates.iter().map(|krate| krate.name().to_owned()));
+    }
+    let second_pass = std::iter::once_with(|| {
+        let update = background_update.take()?;
+        let new_head = match update.join() {
+            Ok(Ok(head)) => head,
+            Ok(Err(e)) => {
+                warn!("background index update failed: {:#}", e);
+                return None;
+            }
+            Err(_) => {
+                warn!("background index update panicked");
+                return None;
+            }
+        };
+        if new_head == index_head {
+            info!("The index update brought no new commits");
+            return None;
+        }
+        let added = Index::new_cargo_default()
+            .map_err(anyhow::Error::from)
+            .and_then(|index| index_diff::changed_crates(&index, index_head, new_head));
+        let mut added = match added {
+            Ok(added) => added,
+            Err(e) => {
+                warn!("can't diff the updated index: {:#}", e);
+                return None;
+            }
+        };
+        added.retain(|krate| {
+            !first_pass.contains(krate.name())
+                && selected
+                    .as_ref()
+                    .is_none_or(|selected| selected.iter().any(|name| name == krate.name()))
+        });
+        info!(
+            "The index update added or changed {} more crates; processing them",
+            added.len()
+        );
+        index_head = new_head;
+        Some(narrow_crates(&args, reference.as_ref(), added))
+    })
+    .flatten()
+    .flatten();
+
+    let metadata_source: Box<dyn CrateMetadataSource + '_> = if clone_phase {
+        Box::new(Stored::new(&conn))
+    } else {
+        match (&args.from_db_dump, &args.replay, &args.record) {
+            (Some(path), _, _) => {
+                let started = Instant::now();
+                let dump = dbdump::DbDump::load(path).with_context(|| {
+                    format!("failed to load the database dump {}", path.display())
+                })?;
+                info!(
+                    "Loaded {} crates from the database dump in {:?}",
+                    dump.len(),
+                    started.elapsed()
+                );
+                Box::new(dump)
+            }
+            (None, Some(dir), _) => Box::new(Replay::new(dir)),
+            (None, None, Some(dir)) => Box::new(Recorder::new(RetryThrottled::new(&client), dir)?),
+            (None, None, None) => Box::new(RetryThrottled::new(&client)),
+        }
+    };
+    // Whether metadata comes from somewhere other than the API, and so is free.
+    let offline_metadata = args.replay.is_some() || args.from_db_dump.is_some() || clone_phase;
+    let harvested = if clone_phase {
+        let names = harvested_crates(&conn).context("failed to read harvested crates")?;
+        info!("{} crates have harvested metadata", names.len());
+        Some(names)
+    } else {
+        None
+    };
+
+    let clone_opts = clone_options(&args);
+    let output_store = match &args.output_url {
+        Some(url) => Some(objstore::open(url)?),
+        None => None,
+    };
+    let clone_rules = match &args.clone_rules {
+        Some(path) => {
+            let rules = rules::Rules::load(path)?;
+            info!("Loaded {} clone rules", rules.len());
+            Some(rules)
+        }
+        None => None,
+    };
+
+    let license_allowlist = args
+        .license_filter
+        .as_deref()
+        .map(license::Allowlist::parse);
+
+    let url_overrides = match &args.repo_url_overrides {
+        Some(path) => {
+            let overrides = overrides::load(path)?;
+            info!("Loaded {} repository URL overrides", overrides.len());
+            overrides
+        }
+        None => HashMap::new(),
+    };
+
+    let advisory_db = if args.scan_advisories {
+        let db = advisories::open_database(&args.advisory_db)?;
+        info!("Loaded {} advisories", db.iter().count());
+        Some(db)
+    } else {
+        None
+    };
+
+    let mut host_probe = if args.preflight_hosts {
+        Some(hosts::HostProbe::new().context("could not create HTTP client")?)
+    } else {
+        None
+    };
+
+    let tarballs = if args.fetch_tarball || args.tarball_only || args.compare_tarball_to_git {
+        Some(tarball::Fetcher::new()?)
+    } else {
+        None
+    };
+
+    let mut repos =
+        SharedRepos::load(&conn, &args.output).context("failed to load cloned repositories")?;
+
+    let statsd = match &args.statsd {
+        Some(addr) => Some(
+            statsd::StatsD::connect(addr, &args.statsd_prefix)
+                .with_context(|| format!("could not set up StatsD at {}", addr))?,
+        ),
+        None => None,
+    };
+    let reporter = console::Reporter::new(args.log_format, statsd);
+    #[cfg(unix)]
+    let reporter = match &args.progress_socket {
+        Some(path) => reporter.with_socket(
+            progress::ProgressSocket::bind(path)
+                .with_context(|| format!("could not listen on {}", path.display()))?,
+        ),
+        None => reporter,
+    };
 
     // ─── Main processing loop ────────────────────────────────────────────────────
-    for krate in crates {
+    let min_free = args
+        .wait_on_disk_full
+        .then_some(args.min_free_mb * 1024 * 1024);
+    let batch = if args.checkpoint_every > 0 {
+        Some(batch::Batch::begin(&conn).context("failed to start a transaction")?)
+    } else {
+        None
+    };
+    let mut processed = 0u64;
+    let mut failures = 0u64;
+    let mut interrupted = false;
+    let mut banned = false;
+    let mut api_calls = 0u64;
+    for krate in crates.into_iter().chain(second_pass) {
+        if let Some(pause_file) = &args.pause_file {
+            wait_while_paused(pause_file, &stop, deadline);
+        }
+        if stop.load(Ordering::SeqCst) || deadline.is_some_and(|d| Instant::now() >= d) {
+            interrupted = true;
+            break;
+        }
+
         let name = krate.name();
-        let dest = args.output.join(name);
+        if let Some(harvested) = &harvested
+            && !harvested.contains(name)
+        {
+            continue;
+        }
+        let (dest, case_collision) = output_dirs.dir_for(name);
+        let crate_opts = clone_rules
+            .as_ref()
+            .and_then(|rules| rules.options_for(name, &clone_opts));
+        let clone_opts = crate_opts.as_ref().unwrap_or(&clone_opts);
 
-        // Skip if we have already cloned this crate successfully
-        let already_cloned: bool = conn
+        // Skip if we have already cloned this crate successfully (or, in a state-only
+        // pass, already have its metadata)
+        let (status, repo_dir): (Option<CrateStatus>, Option<String>) = conn
             .query_row(
-                "SELECT status FROM crates WHERE name = ?1",
+                "SELECT status, repo_dir FROM crates WHERE name = ?1",
                 [name],
-                |row| {
-                    let s: String = row.get(0)?;
-                    Ok(s == "cloned")
-                },
+                |row| Ok((row.get::<_, String>(0)?, row.get(1)?)),
             )
             .optional()
             .context("failed querying status")?
-            .unwrap_or(false);
-        if already_cloned || dest.exists() {
+            .map_or((None, None), |(status, repo_dir)| {
+                (CrateStatus::parse(&status), repo_dir)
+            });
+        let done = match status {
+            Some(
+                CrateStatus::Cloned
+                | CrateStatus::Resumed
+                | CrateStatus::RepoMoved
+                | CrateStatus::SharedRepo
+                | CrateStatus::EditionFiltered
+                | CrateStatus::TestsFiltered,
+            ) => true,
+            Some(CrateStatus::MetadataOk) => args.state_only,
+            Some(CrateStatus::TarballOk) => args.tarball_only,
+            _ => false,
+        };
+        let existing = if done {
+            Existing::Skip
+        } else {
+            existing_checkout(status, &dest, clone_opts, args.clone_into_existing)
+        };
+        let adopt = matches!(existing, Existing::Adopt { .. });
+        // Someone else's checkout, which we never delete.
+        let foreign = existing == Existing::Adopt { ours: false };
+        if existing == Existing::Skip {
+            // A checkout removed since, by hand or by an earlier run, isn't one any more.
+            if let Some(repo_dir) = &repo_dir
+                && !Path::new(repo_dir).exists()
+            {
+                debug!("{} is gone; forgetting it for {}", repo_dir, name);
+                batch::try_write(name, || {
+                    conn.execute("UPDATE crates SET repo_dir = NULL WHERE name = ?1", [name])
+                });
+            }
+            let check_moved = args.detect_moved_repos
+                && matches!(
+                    status,
+                    Some(CrateStatus::Cloned | CrateStatus::Resumed | CrateStatus::RepoMoved)
+                );
+            let refresh = match args.refetch_metadata_after {
+                Some(max_age) => metadata_is_stale(&conn, name, max_age),
+                None => check_moved,
+            };
+            if refresh && args.max_api_calls.is_none_or(|max| api_calls < max) {
+                debug!("refreshing metadata for {}", name);
+                let metadata = metadata_source.get_crate(name);
+                if !offline_metadata {
+                    api_calls += 1;
+                }
+                match metadata {
+                    Ok(resp) => {
+                        if harvest {
+                            store_response(&conn, name, &resp, args.compress_db);
+                        }
+                        if let Err(e) = store_metadata(&conn, name, &resp, args.compress_db) {
+                            warn!("failed to store metadata for {}: {}", name, e);
+                        }
+                        if check_moved {
+                            follow_moved_repo(&conn, &reporter, clone_opts, name, &resp);
+                        }
+                    }
+                    Err(e) if http_status(&e) == Some(403) => {
+                        record_ban(&conn, name, &e);
+                        banned = true;
+                        interrupted = true;
+                        break;
+                    }
+                    Err(e) => warn!("failed to refresh metadata for {}: {}", name, e),
+                }
+            }
             continue;
         }
 
-        match client.get_crate(name) {
-            Ok(resp) => {
-                if let Some(repo) = resp.crate_data.repository {
-                    // Insert or update repository entry with pending status
+        // Every crate from here on needs its metadata.
+        if let Some(max) = args.max_api_calls
+            && !offline_metadata
+            && api_calls >= max
+        {
+            warn!(
+                "Made {} metadata requests; stopping (--max-api-calls)",
+                api_calls
+            );
+            interrupted = true;
+            break;
+        }
+
+        processed += 1;
+        if batch.is_none()
+            && args.checkpoint_interval > 0
+            && processed.is_multiple_of(args.checkpoint_interval)
+        {
+            checkpoint(&conn).context("failed to checkpoint the WAL")?;
+        }
+        if let Some(batch) = &batch
+            && processed.is_multiple_of(args.checkpoint_every)
+        {
+            batch
+                .commit_and_continue(checkpoint)
+                .context("failed to commit the database")?;
+        }
+
+        if args.fetch_dependents || args.min_dependents.is_some() {
+            let max_age = Duration::from_secs(args.dependents_max_age_days * 24 * 60 * 60);
+            let count = reverse_dep_count(&conn, &client, name, max_age);
+            if let (Some(min), Some(count)) = (args.min_dependents, count)
+                && count < min
+            {
+                reporter.report(
+                    name,
+                    CrateStatus::TooFewDependents,
+                    Some(&format!("{} reverse dependencies", count)),
+                );
+                batch::try_write(name, || {
+                    conn.execute(
+                        "UPDATE crates SET status = ?2 WHERE name = ?1",
+                        params![name, CrateStatus::TooFewDependents],
+                    )
+                });
+                continue;
+            }
+        }
+
+        debug!("fetching metadata for {}", name);
+        let started = Instant::now();
+        let metadata = metadata_source.get_crate(name);
+        if !offline_metadata {
+            api_calls += 1;
+        }
+        debug!("metadata for {} took {:?}", name, started.elapsed());
+
+        match metadata {
+            Ok(mut resp) => {
+                // Kept as crates.io sent it, so `clone` applies the overrides current then.
+                if harvest {
+                    store_response(&conn, name, &resp, args.compress_db);
+                }
+                let overridden = match url_overrides.get(name) {
+                    Some(url) => {
+                        debug!("using {} for {} (--repo-url-overrides)", url, name);
+                        resp.crate_data.repository = Some(url.clone());
+                        true
+                    }
+                    None => false,
+                };
+                if let Err(e) = store_metadata(&conn, name, &resp, args.compress_db) {
+                    warn!("failed to store metadata for {}: {}", name, e);
+                }
+                batch::try_write(name, || {
+                    conn.execute(
+                        "UPDATE crates SET overridden = ?2, repository = coalesce(?3, repository) WHERE name = ?1",
+                        params![
+                            name,
+                            overridden,
+                            resp.crate_data.repository.as_deref().filter(|_| overridden),
+                        ],
+                    )
+                });
+                let license = resp
+                    .versions
+                    .iter()
+                    .find(|v| v.num == resp.crate_data.max_version)
+                    .and_then(|v| v.license.as_deref())
+                    .and_then(license::normalize);
+                batch::try_write(name, || {
                     conn.execute(
-                        "INSERT INTO crates (name, repository, status)
-                         VALUES (?1, ?2, 'pending')
-                         ON CONFLICT(name) DO UPDATE SET repository = excluded.repository, status = 'pending'",
-                        params![name, repo],
+                        "UPDATE crates SET license = ?2 WHERE name = ?1",
+                        params![name, license],
                     )
-                    .ok();
+                });
+                if let Some(allowlist) = &license_allowlist
+                    && !license.as_deref().is_some_and(|l| allowlist.allows(l))
+                {
+                    let detail = license.as_deref().unwrap_or("no license on crates.io");
+                    reporter.report(name, CrateStatus::LicenseFiltered, Some(detail));
+                    batch::try_write(name, || {
+                        conn.execute(
+                            "UPDATE crates SET status = ?2 WHERE name = ?1",
+                            params![name, CrateStatus::LicenseFiltered],
+                        )
+                    });
+                    continue;
+                }
+                if let Some(old) = reference.as_ref().and_then(|r| r.repository(name))
+                    && resp.crate_data.repository.as_deref() != Some(old)
+                {
+                    info!(
+                        "~ {} moved from {} to {}",
+                        name,
+                        old,
+                        resp.crate_data
+                            .repository
+                            .as_deref()
+                            .unwrap_or("no repository")
+                    );
+                }
+
+                if args.fetch_owners {
+                    // This goes through the same client, so it waits out the rate limit too.
+                    let started = Instant::now();
+                    match client.crate_owners(name) {
+                        Ok(owners) => {
+                            debug!("owners for {} took {:?}", name, started.elapsed());
+                            if let Err(e) = store_owners(&conn, name, &owners) {
+                                warn!("failed to store owners for {}: {}", name, e);
+                            }
+                        }
+                        Err(e) => warn!("failed to fetch owners for {}: {}", name, e),
+                    }
+                }
+
+                let mut published = None;
+                if let Some(fetcher) = &tarballs {
+                    match fetcher.fetch(&krate, &args.output) {
+                        Ok((version, path)) => {
+                            debug!("downloaded {}", path.display());
+                            batch::try_write(name, || {
+                                conn.execute(
+                                    "UPDATE crates SET tarball_version = ?2 WHERE name = ?1",
+                                    params![name, version],
+                                )
+                            });
+                            if args.tarball_only {
+                                reporter.report(
+                                    name,
+                                    CrateStatus::TarballOk,
+                                    Some(&path.to_string_lossy()),
+                                );
+                                batch::try_write(name, || {
+                                    conn.execute(
+                                        "UPDATE crates SET repository = ?2, status = ?3 WHERE name = ?1",
+                                        params![
+                                            name,
+                                            resp.crate_data.repository,
+                                            CrateStatus::TarballOk
+                                        ],
+                                    )
+                                });
+                                continue;
+                            }
+                            published = Some((version, path));
+                        }
+                        Err(e) if args.tarball_only => {
+                            let error = format!("{:#}", e);
+                            reporter.report(name, CrateStatus::Failed, Some(&error));
+                            failures += 1;
+                            record_failure(
+                                &conn,
+                                name,
+                                CrateStatus::Failed,
+                                &error,
+                                None,
+                                None,
+                                args.compress_db,
+                            );
+                            continue;
+                        }
+                        Err(e) => warn!("failed to download the tarball of {}: {:#}", name, e),
+                    }
+                }
 
-                    match Repository::clone(&repo, &dest) {
-                        Ok(_) => {
-                            println!("✓ cloned {}", name);
+                if let Some(repo) = resp.crate_data.repository {
+                    if args.state_only {
+                        reporter.report(name, CrateStatus::MetadataOk, None);
+                        batch::try_write(name, || {
                             conn.execute(
-                                "UPDATE crates SET status = 'cloned' WHERE name = ?1",
-                                params![name],
+                                "UPDATE crates SET repository = ?2, status = ?3 WHERE name = ?1",
+                                params![name, repo, CrateStatus::MetadataOk],
                             )
-                            .ok();
+                        });
+                        continue;
+                    }
+
+                    // Crates from a repository we already have (monorepos, mostly) are
+                    // found in that checkout instead of being cloned again.
+                    let shared_dir = match repos.claim(&repo, args.max_crates_per_repo) {
+                        Lookup::New => None,
+                        Lookup::Shared(dir) => Some(dir),
+                        Lookup::OverCap => {
+                            reporter.report(
+                                name,
+                                CrateStatus::RepoCap,
+                                Some(&format!("{} is over --max-crates-per-repo", repo)),
+                            );
+                            batch::try_write(name, || {
+                                conn.execute(
+                                    "UPDATE crates SET repository = ?2, status = ?3 WHERE name = ?1",
+                                    params![name, repo, CrateStatus::RepoCap],
+                                )
+                            });
+                            continue;
                         }
-                        Err(e) => {
-                            eprintln!("✗ failed to clone {}: {}", name, e);
+                    };
+                    if let Some(shared_dir) = shared_dir {
+                        let manifest_path = manifest::find_package(&shared_dir, name);
+                        let below_bar = manifest_path
+                            .as_deref()
+                            .and_then(|path| check_filters(&conn, &args, name, path));
+                        match (manifest_path, below_bar) {
+                            (Some(_), Some((status, reason))) => {
+                                reporter.report(name, status, Some(&reason));
+                                batch::try_write(name, || {
+                                    conn.execute(
+                                        "UPDATE crates SET repository = ?2, status = ?4, repo_dir = ?3 WHERE name = ?1",
+                                        params![name, repo, shared_dir.to_string_lossy(), status],
+                                    )
+                                });
+                            }
+                            (Some(manifest_path), None) => {
+                                reporter.report(
+                                    name,
+                                    CrateStatus::SharedRepo,
+                                    Some(&shared_dir.to_string_lossy()),
+                                );
+                                if args.tags_as_versions {
+                                    match git2::Repository::open(&shared_dir) {
+                                        Ok(shared) => record_version_tags(
+                                            &conn,
+                                            &shared,
+                                            name,
+                                            &resp.versions,
+                                        ),
+                                        Err(e) => warn!(
+                                            "failed to open {} for {}: {}",
+                                            shared_dir.display(),
+                                            name,
+                                            e
+                                        ),
+                                    }
+                                }
+                                batch::try_write(name, || {
+                                    conn.execute(
+                                        "UPDATE crates SET repository = ?2, status = ?4, repo_dir = ?3 WHERE name = ?1",
+                                        params![
+                                            name,
+                                            repo,
+                                            shared_dir.to_string_lossy(),
+                                            CrateStatus::SharedRepo
+                                        ],
+                                    )
+                                });
+                                let finished = isolate(args.keep_going_on_panic, || {
+                                    post_clone(
+                                        &conn,
+                                        &args,
+                                        advisory_db.as_ref(),
+                                        &Checkout {
+                                            name,
+                                            repo: &repo,
+                                            dest: &shared_dir,
+                                            manifest_path: &manifest_path,
+                                            published: published.as_ref(),
+                                        },
+                                    )
+                                });
+                                if let Err(message) = finished {
+                                    failures += 1;
+                                    record_panic(&conn, &reporter, &args, name, &message);
+                                }
+                            }
+                            (None, _) => {
+                                reporter.report(
+                                    name,
+                                    CrateStatus::NameMismatch,
+                                    Some(&shared_dir.to_string_lossy()),
+                                );
+                                batch::try_write(name, || {
+                                    conn.execute(
+                                        "UPDATE crates SET repository = ?2, status = ?4, repo_dir = ?3 WHERE name = ?1",
+                                        params![
+                                            name,
+                                            repo,
+                                            shared_dir.to_string_lossy(),
+                                            CrateStatus::NameMismatch
+                                        ],
+                                    )
+                                });
+                            }
+                        }
+                        continue;
+                    }
+
+                    if let Some(Err(e)) = host_probe.as_mut().map(|probe| probe.check(&repo)) {
+                        reporter.report(name, CrateStatus::HostUnreachable, Some(&e));
+                        failures += 1;
+                        batch::try_write(name, || {
                             conn.execute(
-                                "UPDATE crates SET status = 'failed' WHERE name = ?1",
-                                params![name],
+                                "UPDATE crates SET repository = ?2 WHERE name = ?1",
+                                params![name, repo],
                             )
-                            .ok();
+                        });
+                        record_failure(
+                            &conn,
+                            name,
+                            CrateStatus::HostUnreachable,
+                            &e,
+                            None,
+                            None,
+                            args.compress_db,
+                        );
+                        continue;
+                    }
+
+                    // Insert or update repository entry with pending status
+                    batch::try_write(name, || {
+                        conn.execute(
+                            "INSERT INTO crates (name, repository, status)
+                             VALUES (?1, ?2, ?3)
+                             ON CONFLICT(name) DO UPDATE SET repository = excluded.repository, status = excluded.status",
+                            params![name, repo, CrateStatus::Pending],
+                        )
+                    });
+
+                    if case_collision {
+                        warn!(
+                            "{} differs only in case from a directory in {}; cloning into {}",
+                            name,
+                            args.output.display(),
+                            dest.display()
+                        );
+                    }
+                    if let Some(dir_name) = dest.file_name() {
+                        output_dirs.claim(&dir_name.to_string_lossy());
+                    }
+                    if let Some(min_free) = min_free {
+                        wait_for_space(&args.output, min_free, &stop, deadline);
+                    }
+                    let started = Instant::now();
+                    let cloned = isolate(args.keep_going_on_panic, || {
+                        if adopt {
+                            debug!("adopting {} for {}", dest.display(), name);
+                            clone::adopt(&dest, &repo)
+                        } else {
+                            debug!("cloning {} from {}", name, repo);
+                            clone::clone(name, &repo, &dest, clone_opts)
+                        }
+                    });
+                    let mut cloned = match cloned {
+                        Ok(cloned) => cloned,
+                        Err(message) => {
+                            failures += 1;
+                            record_panic(&conn, &reporter, &args, name, &message);
+                            continue;
+                        }
+                    };
+                    if let Some(min_free) = min_free
+                        && !adopt
+                        && cloned.as_ref().err().is_some_and(CloneError::is_disk_full)
+                    {
+                        warn!("ran out of disk space cloning {}", name);
+                        if dest.exists() {
+                            std::fs::remove_dir_all(&dest).ok();
+                        }
+                        wait_for_space(&args.output, min_free, &stop, deadline);
+                        cloned = clone::clone(name, &repo, &dest, clone_opts);
+                    }
+                    debug!("clone of {} took {:?}", name, started.elapsed());
+
+                    match cloned {
+                        Ok(cloned) => {
+                            if !adopt {
+                                reporter.clone_finished(started.elapsed(), &dest);
+                            }
+                            if cloned.repo.is_empty().unwrap_or(false) {
+                                reporter.report(name, CrateStatus::EmptyRepo, None);
+                                batch::try_write(name, || {
+                                    conn.execute(
+                                        "UPDATE crates SET status = ?2 WHERE name = ?1",
+                                        params![name, CrateStatus::EmptyRepo],
+                                    )
+                                });
+                                if args.remove_empty_repos
+                                    && !foreign
+                                    && let Err(e) = std::fs::remove_dir_all(&dest)
+                                {
+                                    warn!("failed to remove empty clone of {}: {}", name, e);
+                                }
+                                continue;
+                            }
+                            if args.tags_as_versions {
+                                record_version_tags(&conn, &cloned.repo, name, &resp.versions);
+                            }
+                            if args.checkout_version_tag || args.only_newest_version_with_tag {
+                                let tagged = checkout_version_tag(
+                                    &conn,
+                                    &args,
+                                    &cloned.repo,
+                                    name,
+                                    &resp.versions,
+                                );
+                                if args.verify_head_matches_tag
+                                    && let Some((tag, commit)) = tagged
+                                    && let Err(error) = verify_head(&cloned.repo, &tag, commit)
+                                {
+                                    reporter.report(
+                                        name,
+                                        CrateStatus::HeadTagMismatch,
+                                        Some(&error),
+                                    );
+                                    failures += 1;
+                                    record_failure(
+                                        &conn,
+                                        name,
+                                        CrateStatus::HeadTagMismatch,
+                                        &error,
+                                        None,
+                                        None,
+                                        args.compress_db,
+                                    );
+                                    batch::try_write(name, || {
+                                        conn.execute(
+                                            "UPDATE crates SET repo_dir = ?2 WHERE name = ?1",
+                                            params![name, dest.to_string_lossy()],
+                                        )
+                                    });
+                                    continue;
+                                }
+                            }
+                            let manifest_path = manifest::find_package(&dest, name);
+                            let below_bar = manifest_path
+                                .as_deref()
+                                .and_then(|path| check_filters(&conn, &args, name, path));
+                            match (manifest_path, below_bar) {
+                                (Some(_), Some((status, reason))) => {
+                                    reporter.report(name, status, Some(&reason));
+                                    let remove = args.remove_filtered && !foreign;
+                                    let removed = remove
+                                        && std::fs::remove_dir_all(&dest)
+                                            .inspect_err(|e| {
+                                                warn!(
+                                                    "failed to remove filtered clone of {}: {}",
+                                                    name, e
+                                                )
+                                            })
+                                            .is_ok();
+                                    // No repo_dir for a checkout that's gone.
+                                    let repo_dir = (!removed).then(|| dest.to_string_lossy());
+                                    batch::try_write(name, || {
+                                        conn.execute(
+                                            "UPDATE crates SET status = ?3, repo_dir = ?2 WHERE name = ?1",
+                                            params![name, repo_dir, status],
+                                        )
+                                    });
+                                    if remove {
+                                        continue;
+                                    }
+                                }
+                                (Some(manifest_path), None) => {
+                                    if args.skip_vendored {
+                                        skip_vendored(&conn, name, &dest, &manifest_path);
+                                    }
+                                    record_lfs(&conn, &args, name, &dest);
+                                    let status = if cloned.resumed {
+                                        CrateStatus::Resumed
+                                    } else {
+                                        CrateStatus::Cloned
+                                    };
+                                    reporter.report(name, status, None);
+                                    batch::try_write(name, || {
+                                        conn.execute(
+                                            "UPDATE crates SET status = ?2, clone_protocol = ?3, repo_dir = ?4, resolved_url = ?5 WHERE name = ?1",
+                                            params![
+                                                name,
+                                                status,
+                                                cloned.protocol(),
+                                                dest.to_string_lossy(),
+                                                cloned.redirected.then_some(&cloned.url),
+                                            ],
+                                        )
+                                    });
+                                    if args.output_manifest_per_crate {
+                                        let sidecar = sidecar::Sidecar {
+                                            name,
+                                            version: &resp.crate_data.max_version,
+                                            repository: &repo,
+                                            head: cloned
+                                                .repo
+                                                .head()
+                                                .ok()
+                                                .and_then(|head| head.target())
+                                                .map(|id| id.to_string()),
+                                            cloned_at: unix_now(),
+                                        };
+                                        if let Err(e) = sidecar::write(&dest, &sidecar) {
+                                            warn!(
+                                                "failed to write {} for {}: {}",
+                                                sidecar::FILE_NAME,
+                                                name,
+                                                e
+                                            );
+                                        }
+                                    }
+                                    let finished = isolate(args.keep_going_on_panic, || {
+                                        post_clone(
+                                            &conn,
+                                            &args,
+                                            advisory_db.as_ref(),
+                                            &Checkout {
+                                                name,
+                                                repo: &repo,
+                                                dest: &dest,
+                                                manifest_path: &manifest_path,
+                                                published: published.as_ref(),
+                                            },
+                                        )
+                                    });
+                                    if let Err(message) = finished {
+                                        failures += 1;
+                                        record_panic(&conn, &reporter, &args, name, &message);
+                                    }
+                                }
+                                (None, _) => {
+                                    reporter.report(
+                                        name,
+                                        CrateStatus::NameMismatch,
+                                        Some(&dest.to_string_lossy()),
+                                    );
+                                    batch::try_write(name, || {
+                                        conn.execute(
+                                            "UPDATE crates SET status = ?3, repo_dir = ?2 WHERE name = ?1",
+                                            params![
+                                                name,
+                                                dest.to_string_lossy(),
+                                                CrateStatus::NameMismatch
+                                            ],
+                                        )
+                                    });
+                                }
+                            }
+                            // Filtered and name-mismatched checkouts that were kept go up
+                            // too; one --clone-into-existing adopted isn't ours to delete.
+                            if let Some(store) = &output_store
+                                && dest.exists()
+                                && !foreign
+                            {
+                                match upload(store.as_ref(), &dest) {
+                                    Ok(url) => {
+                                        debug!("uploaded {} to {}", name, url);
+                                        batch::try_write(name, || {
+                                            conn.execute(
+                                                "UPDATE crates SET archive_url = ?2, repo_dir = NULL WHERE name = ?1",
+                                                params![name, url],
+                                            )
+                                        });
+                                    }
+                                    Err(e) => {
+                                        let error = format!("{:#}", e);
+                                        reporter.report(name, CrateStatus::Failed, Some(&error));
+                                        failures += 1;
+                                        record_failure(
+                                            &conn,
+                                            name,
+                                            CrateStatus::Failed,
+                                            &error,
+                                            None,
+                                            None,
+                                            args.compress_db,
+                                        );
+                                    }
+                                }
+                                // Gone either way: a failed crate is cloned again next run.
+                                if let Err(e) = std::fs::remove_dir_all(&dest) {
+                                    warn!("failed to remove {}: {}", dest.display(), e);
+                                }
+                            }
+                            if dest.exists() {
+                                repos.insert(&repo, dest.clone());
+                            }
+                        }
+                        Err(e @ CloneError::AuthFailed { .. }) => {
+                            let error = e.to_string();
+                            reporter.report(name, CrateStatus::AuthRequired, Some(&error));
+                            failures += 1;
+                            record_failure(
+                                &conn,
+                                name,
+                                CrateStatus::AuthRequired,
+                                &error,
+                                None,
+                                e.git_code(),
+                                args.compress_db,
+                            );
+                        }
+                        Err(e) => {
+                            let error = e.to_string();
+                            let status = if e.is_tls_error() {
+                                CrateStatus::TlsError
+                            } else {
+                                CrateStatus::Failed
+                            };
+                            reporter.report(name, status, Some(&error));
+                            failures += 1;
+                            record_failure(
+                                &conn,
+                                name,
+                                status,
+                                &error,
+                                None,
+                                e.git_code(),
+                                args.compress_db,
+                            );
                         }
                     }
                 } else {
-                    eprintln!("ℹ no repository URL for {}", name);
-                    conn.execute(
-                        "INSERT INTO crates (name, repository, status)
-                         VALUES (?1, NULL, 'no_repo')
-                         ON CONFLICT(name) DO UPDATE SET status = 'no_repo'",
-                        params![name],
-                    )
-                    .ok();
+                    reporter.report(name, CrateStatus::NoRepo, None);
+                    if args.skip_no_repo_rows {
+                        if let Err(e) = forget_crate(&conn, name) {
+                            warn!("failed to remove {} from the database: {}", name, e);
+                        }
+                    } else {
+                        batch::try_write(name, || {
+                            conn.execute(
+                                "INSERT INTO crates (name, repository, status)
+                                 VALUES (?1, NULL, ?2)
+                                 ON CONFLICT(name) DO UPDATE SET status = excluded.status",
+                                params![name, CrateStatus::NoRepo],
+                            )
+                        });
+                    }
                 }
             }
+            // The endpoint is public, so a 403 is crates.io blocking us, not this crate:
+            // every crate after it would fail the same way.
+            Err(e) if http_status(&e) == Some(403) => {
+                record_ban(&conn, name, &e);
+                banned = true;
+                interrupted = true;
+                break;
+            }
             Err(e) => {
-                eprintln!("✗ failed to fetch metadata for {}: {}", name, e);
-                conn.execute(
-                    "INSERT INTO crates (name, repository, status)
-                     VALUES (?1, NULL, 'metadata_error')
-                     ON CONFLICT(name) DO UPDATE SET status = 'metadata_error'",
-                    params![name],
-                )
-                .ok();
+                let error = format!("{:#}", e);
+                reporter.report(name, CrateStatus::MetadataError, Some(&error));
+                failures += 1;
+                record_failure(
+                    &conn,
+                    name,
+                    CrateStatus::MetadataError,
+                    &error,
+                    http_status(&e),
+                    None,
+                    args.compress_db,
+                );
             }
         }
     }
 
+    if let Some(batch) = batch {
+        batch.commit().context("failed to commit the database")?;
+    }
+    // Stopped before the second pass: let the update finish rather than cut it off.
+    if let Some(update) = background_update.take() {
+        update.join().ok();
+    }
+
+    // An interrupted run hasn't seen every crate in this index commit, so the next
+    // --only-changed run must still diff from the previous one.
+    if args.max_api_calls.is_some() {
+        info!("Made {} crates.io metadata requests", api_calls);
+    }
+    if interrupted {
+        warn!("Stopped early after {} crates", processed);
+    } else {
+        set_meta(&conn, LAST_INDEX_COMMIT, &index_head.to_string())
+            .context("failed to record index commit")?;
+    }
+
+    // ─── Database maintenance ────────────────────────────────────────────────────
+    checkpoint(&conn).context("failed to checkpoint the WAL")?;
+    if args.vacuum {
+        conn.execute_batch("VACUUM")
+            .context("failed to vacuum the database")?;
+    }
+    if let Some(path) = &args.backup {
+        backup(&conn, path).context("failed to write database backup")?;
+        info!("Wrote database backup to {}", path.display());
+    }
+
+    let code = if banned {
+        ExitCode::from(EXIT_BANNED)
+    } else if args.always_exit_zero {
+        ExitCode::SUCCESS
+    } else if interrupted {
+        ExitCode::from(EXIT_INTERRUPTED)
+    } else if failures > 0 {
+        ExitCode::from(EXIT_FAILURES)
+    } else {
+        ExitCode::SUCCESS
+    };
+    Ok(code)
+}
+
+/// Fetch the latest index into the local copy and return its new head commit, for
+/// `--background-index-update`. Uses its own handle, since the main one keeps reading
+/// the commit it was opened at.
+fn update_index() -> Result<Oid> {
+    let mut index = Index::new_cargo_default().context("could not open crates.io index")?;
+    index.update().context("could not update crates.io index")?;
+    index_diff::head_commit(&index)
+}
+
+/// Narrow `crates` down by --diff-against, --since-crate/--until-crate and --shard.
+fn narrow_crates(args: &Args, reference: Option<&Reference>, crates: Vec<Crate>) -> Vec<Crate> {
+    let crates = match reference {
+        Some(reference) => {
+            let changed: Vec<_> = crates
+                .into_iter()
+                .filter(|krate| match reference.change(krate) {
+                    Some(Change::New) => {
+                        info!("+ {} is new", krate.name());
+                        true
+                    }
+                    Some(Change::Version { from, to }) => {
+                        info!("~ {} changed from {} to {}", krate.name(), from, to);
+                        true
+                    }
+                    None => false,
+                })
+                .collect();
+            info!(
+                "{} crates are new or changed since the reference database",
+                changed.len()
+            );
+            changed
+        }
+        None => crates,
+    };
+    let crates = if args.since_crate.is_some() || args.until_crate.is_some() {
+        let since = args.since_crate.as_deref().map(str::to_ascii_lowercase);
+        let until = args.until_crate.as_deref().map(str::to_ascii_lowercase);
+        let in_range: Vec<_> = crates
+            .into_iter()
+            .filter(|krate| {
+                let name = krate.name().to_ascii_lowercase();
+                since.as_ref().is_none_or(|since| &name >= since)
+                    && until.as_ref().is_none_or(|until| &name < until)
+            })
+            .collect();
+        info!("{} crates are in the requested name range", in_range.len());
+        in_range
+    } else {
+        crates
+    };
+    match args.shard {
+        Some(shard) => {
+            let mine: Vec<_> = crates
+                .into_iter()
+                .filter(|krate| shard.contains(krate.name()))
+                .collect();
+            info!(
+                "{} crates are in shard {}/{}",
+                mine.len(),
+                shard.index,
+                shard.count
+            );
+            mine
+        }
+        None => crates,
+    }
+}
+
+/// Every crate in the index, or just the `selected` ones.
+fn all_crates(index: &Index, selected: Option<&[String]>) -> Vec<Crate> {
+    match selected {
+        Some(names) => names.iter().filter_map(|name| index.crate_(name)).collect(),
+        None => index.crates().collect(),
+    }
+}
+
+/// Log a statement about to run, with its parameters already substituted, for
+/// `--print-sql`.
+fn print_sql(sql: &str) {
+    debug!("SQL: {}", sql);
+}
+
+fn init_tls(args: &Args) -> Result<()> {
+    tls::init(tls::TlsOptions {
+        ca_bundle: args.ca_bundle.clone(),
+        insecure: args.insecure_tls,
+    })?;
+    if args.insecure_tls {
+        warn!(
+            "--insecure-tls: NOT verifying TLS certificates; anyone on the network path \
+             can impersonate repository hosts and tamper with what gets cloned"
+        );
+    }
+    Ok(())
+}
+
+fn clone_options(args: &Args) -> CloneOptions {
+    CloneOptions {
+        auth: Auth {
+            ssh_key: args.ssh_key.clone(),
+            token: args.git_token.clone(),
+            max_attempts: args.max_auth_attempts,
+        },
+        shared_objects: args.shared_objects.clone(),
+        protocol: args.clone_protocol,
+        exact_bytes: !args.normalize_line_endings,
+        git_env: args.clone_env.clone(),
+    }
+}
+
+/// Parse the command line, with defaults taken from the `--config` file if there is one.
+fn parse_args() -> Result<Args> {
+    let mut cmd = Args::command();
+    let path = config::path_from_args(std::env::args_os())
+        .or_else(|| std::env::var_os("BUGBOT_CONFIG").map(PathBuf::from));
+    if let Some(path) = path {
+        cmd = config::apply(cmd, &config::load(&path)?)?;
+    }
+    let mut matches = cmd.get_matches();
+    Ok(Args::from_arg_matches_mut(&mut matches).unwrap_or_else(|e| e.exit()))
+}
+
+/// A `--dump-status` value, pointing at `--list-statuses` when it isn't one.
+fn parse_status(s: &str) -> Result<CrateStatus, String> {
+    CrateStatus::parse(s).ok_or_else(|| format!("unknown status `{}` (see --list-statuses)", s))
+}
+
+/// Crate names from a `--crates-file`.
+fn read_crate_list(path: &Path) -> std::io::Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Write the names of crates in any of `statuses` to `path`, one per line, for
+/// `--dump-failed`. Returns how many there were.
+fn dump_crates(path: &Path, statuses: &[CrateStatus]) -> Result<usize> {
+    let conn = Connection::open_with_flags("bugbot.sqlite", OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let marks = vec!["?"; statuses.len()].join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT name FROM crates WHERE status IN ({marks}) ORDER BY name"
+    ))?;
+    let names = stmt
+        .query_map(rusqlite::params_from_iter(statuses), |row| {
+            row.get::<_, String>(0)
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut text = names.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    std::fs::write(path, text).with_context(|| format!("could not write {}", path.display()))?;
+    Ok(names.len())
+}
+
+/// Print the number of crates in each status, most common first, and the total.
+fn print_status() -> Result<()> {
+    let conn = Connection::open_with_flags("bugbot.sqlite", OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(
+        "SELECT status, COUNT(*) FROM crates GROUP BY status ORDER BY COUNT(*) DESC, status",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+    })?;
+    let mut total = 0;
+    for row in rows {
+        let (status, count) = row?;
+        println!("{:<20} {:>8}", status, count);
+        total += count;
+    }
+    println!("{:<20} {:>8}", "total", total);
+    Ok(())
+}
+
+/// What to do about whatever is at a crate's checkout directory, for a crate that
+/// isn't done yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Existing {
+    /// Clone it, picking up an interrupted clone if there is one.
+    Clone,
+    /// Take over the checkout that's there. `ours` is a clone of our own whose row was
+    /// lost, because the batch recording it never committed before a crash or a second
+    /// Ctrl-C; otherwise it's anyone's, for --clone-into-existing.
+    Adopt { ours: bool },
+    /// Leave it alone: something else is there.
+    Skip,
+}
+
+/// What to do about `dest` for a crate the database has in `status`.
+fn existing_checkout(
+    status: Option<CrateStatus>,
+    dest: &Path,
+    opts: &CloneOptions,
+    clone_into_existing: bool,
+) -> Existing {
+    // A checkout left behind by a crate still marked pending is an interrupted clone,
+    // which clone::clone picks back up.
+    if !dest.exists() || (status == Some(CrateStatus::Pending) && clone::is_resumable(dest, opts)) {
+        Existing::Clone
+    } else if status.is_none() {
+        // Clones are only renamed to `dest` once complete, so this is a whole one.
+        Existing::Adopt { ours: true }
+    } else if clone_into_existing {
+        Existing::Adopt { ours: false }
+    } else {
+        Existing::Skip
+    }
+}
+
+/// Parse a duration like `90`, `90s`, `15m`, `12h` or `2d` (bare numbers are seconds).
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", s))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown unit `{}` in duration (use s, m, h or d)",
+                unit
+            ))
+        }
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration `{}` is out of range", s))
+}
+
+/// How often a paused run checks whether the pause file is gone.
+const PAUSE_POLL: Duration = Duration::from_secs(2);
+
+/// Block while `pause_file` exists, unless the run is stopped or out of time meanwhile.
+fn wait_while_paused(pause_file: &Path, stop: &AtomicBool, deadline: Option<Instant>) {
+    if !pause_file.exists() {
+        return;
+    }
+    info!("Paused; remove {} to continue", pause_file.display());
+    while pause_file.exists() {
+        if stop.load(Ordering::SeqCst) || deadline.is_some_and(|d| Instant::now() >= d) {
+            return;
+        }
+        std::thread::sleep(PAUSE_POLL);
+    }
+    info!("Resuming");
+}
+
+/// One of `count` disjoint slices of the index, for `--shard`.
+#[derive(Clone, Copy)]
+struct Shard {
+    index: u64,
+    count: u64,
+}
+
+impl Shard {
+    /// Whether crate `name` belongs to this shard. Uses 64-bit FNV-1a, which unlike
+    /// std's hasher is the same on every machine and every release.
+    fn contains(self, name: &str) -> bool {
+        let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte.to_ascii_lowercase())).wrapping_mul(0x0100_0000_01b3)
+        });
+        hash % self.count == self.index
+    }
+}
+
+/// A count flag's value from the environment or the config file: a number, or true or
+/// false like the switches.
+fn parse_count(s: &str) -> Result<u8, String> {
+    match s {
+        "true" => Ok(1),
+        "false" => Ok(0),
+        _ => s
+            .parse()
+            .map_err(|_| format!("expected a count or true/false, got `{}`", s)),
+    }
+}
+
+/// A `--progress-socket` path, on platforms that have Unix domain sockets.
+fn parse_socket_path(s: &str) -> Result<PathBuf, String> {
+    if cfg!(unix) {
+        Ok(PathBuf::from(s))
+    } else {
+        Err("progress sockets are Unix domain sockets, which this platform doesn't have".into())
+    }
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("expected KEY=VALUE, got `{}`", s)),
+    }
+}
+
+fn parse_min_rust_version(s: &str) -> Result<(u64, u64, u64), String> {
+    manifest::parse_rust_version(s)
+        .ok_or_else(|| format!("invalid rust version `{}` (expected e.g. 1.70)", s))
+}
+
+/// Parse a shard like `2/8`.
+fn parse_shard(s: &str) -> Result<Shard, String> {
+    let (index, count) = s
+        .split_once('/')
+        .ok_or_else(|| format!("invalid shard `{}` (expected I/N, e.g. 0/4)", s))?;
+    let index: u64 = index
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid shard index `{}`", index))?;
+    let count: u64 = count
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid shard count `{}`", count))?;
+    if index >= count {
+        return Err(format!(
+            "shard index {} must be below the shard count {}",
+            index, count
+        ));
+    }
+    Ok(Shard { index, count })
+}
+
+/// How often a run waiting for disk space checks again.
+const DISK_POLL: Duration = Duration::from_secs(30);
+
+/// Block until the filesystem holding `dir` has `min_free` bytes available, unless the
+/// run is stopped or out of time meanwhile. Gives up quietly if free space can't be
+/// read.
+fn wait_for_space(dir: &Path, min_free: u64, stop: &AtomicBool, deadline: Option<Instant>) {
+    let free = || fs2::available_space(dir).unwrap_or(u64::MAX);
+    if free() >= min_free {
+        return;
+    }
+    warn!(
+        "Less than {} MiB free in {}; waiting for space",
+        min_free / (1024 * 1024),
+        dir.display()
+    );
+    while free() < min_free {
+        if stop.load(Ordering::SeqCst) || deadline.is_some_and(|d| Instant::now() >= d) {
+            return;
+        }
+        std::thread::sleep(DISK_POLL);
+    }
+    info!("Disk space is available again; continuing");
+}
+
+/// `meta` key holding the index commit the last completed run processed.
+const LAST_INDEX_COMMIT: &str = "last_index_commit";
+
+fn get_meta(conn: &Connection, key: &str) -> rusqlite::Result<Option<String>> {
+    conn.query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+fn set_meta(conn: &Connection, key: &str, value: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, value],
+    )
+    .map(|_| ())
+}
+
+/// Fold the write-ahead log back into the database and truncate it, so the `-wal`
+/// file doesn't keep growing over a multi-day run.
+fn checkpoint(conn: &Connection) -> rusqlite::Result<()> {
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+}
+
+/// Snapshot the database with `VACUUM INTO` and gzip the snapshot to `path`.
+fn backup(conn: &Connection, path: &Path) -> Result<()> {
+    let snapshot = path.with_extension("snapshot");
+    if snapshot.exists() {
+        std::fs::remove_file(&snapshot)?;
+    }
+    conn.execute("VACUUM INTO ?1", params![snapshot.to_string_lossy()])?;
+
+    let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+    std::io::copy(&mut File::open(&snapshot)?, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(&snapshot)?;
     Ok(())
 }
 
+/// Check out the tag of the version picked from `versions` and record which version
+/// that was. The default branch stays checked out when no matching tag exists.
+///
+/// Only the newest version is tried, unless `--only-newest-version-with-tag` lets
+/// older ones stand in for it. Returns the tag picked and its commit, even if checking
+/// it out failed.
+fn checkout_version_tag(
+    conn: &Connection,
+    args: &Args,
+    repo: &git2::Repository,
+    name: &str,
+    versions: &[Version],
+) -> Option<(String, git2::Oid)> {
+    let candidates = tags::versions_newest_first(versions, args.include_yanked_versions);
+    let Some(newest) = candidates.first() else {
+        debug!("no eligible version of {} to check out", name);
+        return None;
+    };
+    let tries = if args.only_newest_version_with_tag {
+        candidates.len()
+    } else {
+        1
+    };
+    let found = candidates.iter().take(tries).find_map(|version| {
+        tags::find_version_tag(repo, name, &version.num).map(|(tag, commit)| (version, tag, commit))
+    });
+    let Some((version, tag, commit)) = found else {
+        debug!(
+            "no tag for {} {}; leaving the default branch checked out",
+            name, newest.num
+        );
+        return None;
+    };
+    if version.num != newest.num {
+        debug!(
+            "{} {} isn't tagged; using {} instead",
+            name, newest.num, version.num
+        );
+    }
+    if let Err(e) = tags::checkout(repo, commit) {
+        warn!("failed to check out {} for {}: {}", tag, name, e);
+        return Some((tag, commit));
+    }
+    debug!("checked out {} for {} {}", tag, name, version.num);
+    batch::try_write(name, || {
+        conn.execute(
+            "UPDATE crates SET checked_out_version = ?2, checked_out_yanked = ?3 WHERE name = ?1",
+            params![name, version.num, version.yanked],
+        )
+    });
+    Some((tag, commit))
+}
+
+/// Check that `repo`'s HEAD is `commit`, which `tag` resolved to.
+fn verify_head(repo: &git2::Repository, tag: &str, commit: git2::Oid) -> Result<(), String> {
+    let head = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(|e| format!("could not read HEAD: {}", e))?;
+    if head.id() == commit {
+        Ok(())
+    } else {
+        Err(format!("HEAD is {} but {} is {}", head.id(), tag, commit))
+    }
+}
+
+/// Replace the stored version-to-commit mapping of `name` with every version in
+/// `versions` that has a tag in `repo`.
+fn record_version_tags(
+    conn: &Connection,
+    repo: &git2::Repository,
+    name: &str,
+    versions: &[Version],
+) {
+    let tagged: Vec<_> = versions
+        .iter()
+        .filter_map(|version| {
+            let (tag, commit) = tags::find_version_tag(repo, name, &version.num)?;
+            Some((version, tag, commit))
+        })
+        .collect();
+    debug!(
+        "{} of {} versions of {} are tagged",
+        tagged.len(),
+        versions.len(),
+        name
+    );
+    if let Err(e) = store_version_tags(conn, name, &tagged) {
+        warn!("failed to store version tags for {}: {}", name, e);
+    }
+}
+
+fn store_version_tags(
+    conn: &Connection,
+    name: &str,
+    tagged: &[(&Version, String, Oid)],
+) -> rusqlite::Result<()> {
+    let tx = batch::Savepoint::new(conn)?;
+    tx.execute("DELETE FROM versions WHERE crate_name = ?1", params![name])?;
+    for (version, tag, commit) in tagged {
+        tx.execute(
+            "INSERT INTO versions (crate_name, version, tag, commit_id, yanked)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![name, version.num, tag, commit.to_string(), version.yanked],
+        )?;
+    }
+    tx.commit()
+}
+
+/// Note whether `dest` uses Git LFS and, with `--fetch-lfs`, replace its pointer files
+/// with the real content.
+fn record_lfs(conn: &Connection, args: &Args, name: &str, dest: &Path) {
+    if !lfs::uses_lfs(dest) {
+        return;
+    }
+    let state = if !args.fetch_lfs {
+        debug!("{} uses Git LFS; keeping pointer files", name);
+        "pointers_only"
+    } else {
+        match lfs::pull(dest) {
+            Ok(()) => "fetched",
+            Err(e) => {
+                warn!("failed to fetch LFS objects for {}: {}", name, e);
+                "pointers_only"
+            }
+        }
+    };
+    batch::try_write(name, || {
+        conn.execute(
+            "UPDATE crates SET lfs = ?2 WHERE name = ?1",
+            params![name, state],
+        )
+    });
+}
+
+/// Sparse-check out `dest` without the vendored C sources of the package at
+/// `manifest_path`, and record whether any were left out.
+fn skip_vendored(conn: &Connection, name: &str, dest: &Path, manifest_path: &Path) {
+    let dirs = vendored::find(manifest_path.parent().unwrap_or(dest));
+    let excluded = !dirs.is_empty()
+        && match vendored::exclude(dest, &dirs) {
+            Ok(()) => {
+                debug!("left {:?} out of the checkout of {}", dirs, name);
+                true
+            }
+            Err(e) => {
+                warn!("failed to leave vendored sources out of {}: {}", name, e);
+                false
+            }
+        };
+    batch::try_write(name, || {
+        conn.execute(
+            "UPDATE crates SET vendored_excluded = ?2 WHERE name = ?1",
+            params![name, excluded],
+        )
+    });
+}
+
+/// Run the filters that need a checkout, and return the status to record `name` with
+/// and why, if one rejects it.
+fn check_filters(
+    conn: &Connection,
+    args: &Args,
+    name: &str,
+    manifest_path: &Path,
+) -> Option<(CrateStatus, String)> {
+    if let Some(reason) = check_rust_target(conn, args, name, manifest_path) {
+        return Some((CrateStatus::EditionFiltered, reason));
+    }
+    if args.scan_tests || args.require_tests {
+        let package_dir = manifest_path.parent().unwrap_or(manifest_path);
+        let has_tests = scan::has_tests(package_dir);
+        batch::try_write(name, || {
+            conn.execute(
+                "UPDATE crates SET has_tests = ?2 WHERE name = ?1",
+                params![name, has_tests],
+            )
+        });
+        if args.require_tests && !has_tests {
+            return Some((
+                CrateStatus::TestsFiltered,
+                "no tests/ directory or #[test] functions".to_owned(),
+            ));
+        }
+    }
+    None
+}
+
+/// Record the edition and rust-version of `name`'s manifest, and return why it falls
+/// short of --min-edition or --min-rust-version, if it does.
+fn check_rust_target(
+    conn: &Connection,
+    args: &Args,
+    name: &str,
+    manifest_path: &Path,
+) -> Option<String> {
+    let target = manifest::rust_target(manifest_path);
+    batch::try_write(name, || {
+        conn.execute(
+            "UPDATE crates SET edition = ?2, rust_version = ?3 WHERE name = ?1",
+            params![name, target.edition, target.rust_version],
+        )
+    });
+
+    if let Some(min) = args.min_edition {
+        let edition = target.edition.as_deref().unwrap_or("2015");
+        if edition.parse::<u16>().is_ok_and(|year| year < min) {
+            return Some(format!("edition {} is older than {}", edition, min));
+        }
+    }
+    if let Some(min) = args.min_rust_version {
+        let (major, minor, patch) = min;
+        let declared = target.rust_version.as_deref();
+        match declared.and_then(manifest::parse_rust_version) {
+            Some(version) if version >= min => {}
+            Some(_) => {
+                return Some(format!(
+                    "rust-version {} is older than {}.{}.{}",
+                    declared.unwrap_or_default(),
+                    major,
+                    minor,
+                    patch
+                ));
+            }
+            None => return Some("no rust-version declared".to_owned()),
+        }
+    }
+    None
+}
+
+/// The crate [`post_clone`] runs against and where its checkout is.
+struct Checkout<'a> {
+    name: &'a str,
+    repo: &'a str,
+    dest: &'a Path,
+    manifest_path: &'a Path,
+    /// The published version and its downloaded `.crate`, for `--compare-tarball-to-git`.
+    published: Option<&'a (String, PathBuf)>,
+}
+
+/// Everything that runs against a successful clone: the user's hook and the optional
+/// scans. Failures here are logged but never change the crate's status.
+fn post_clone(conn: &Connection, args: &Args, advisory_db: Option<&Database>, checkout: &Checkout) {
+    let &Checkout {
+        name,
+        repo,
+        dest,
+        manifest_path,
+        published,
+    } = checkout;
+    let package_dir = manifest_path.parent().unwrap_or(dest);
+    if let Some(license) = license::from_files(package_dir).or_else(|| license::from_files(dest)) {
+        batch::try_write(name, || {
+            conn.execute(
+                "UPDATE crates SET license = ?2 WHERE name = ?1 AND license IS NULL",
+                params![name, license],
+            )
+        });
+    }
+
+    if let Some(db) = advisory_db {
+        match advisories::scan(db, dest, manifest_path) {
+            Ok(findings) => {
+                if let Err(e) = store_findings(conn, name, findings.as_deref()) {
+                    warn!("failed to store advisories for {}: {}", name, e);
+                }
+            }
+            Err(e) => warn!("failed to scan {} for advisories: {:#}", name, e),
+        }
+    }
+
+    if args.scan_unsafe {
+        let count = scan::count_unsafe(package_dir);
+        debug!("{} has {} unsafe items", name, count);
+        batch::try_write(name, || {
+            conn.execute(
+                "UPDATE crates SET unsafe_count = ?2 WHERE name = ?1",
+                params![name, count],
+            )
+        });
+    }
+
+    if args.compare_tarball_to_git
+        && let Some((version, crate_file)) = published
+    {
+        compare_tarball(conn, name, version, crate_file, dest, package_dir);
+    }
+
+    if let Some(cmd) = &args.post_clone_hook {
+        match hook::run(cmd, name, dest, repo, &args.hook_log) {
+            Ok(code) => {
+                if code != Some(0) {
+                    warn!("post-clone hook for {} exited with {:?}", name, code);
+                }
+                batch::try_write(name, || {
+                    conn.execute(
+                        "UPDATE crates SET hook_status = ?2 WHERE name = ?1",
+                        params![name, code],
+                    )
+                });
+            }
+            Err(e) => warn!("failed to run post-clone hook for {}: {}", name, e),
+        }
+    }
+}
+
+/// Diff the published `version` of `name` against its checkout and replace the stored
+/// discrepancies with what's found.
+fn compare_tarball(
+    conn: &Connection,
+    name: &str,
+    version: &str,
+    crate_file: &Path,
+    dest: &Path,
+    package_dir: &Path,
+) {
+    let repo = match git2::Repository::open(dest) {
+        Ok(repo) => repo,
+        Err(e) => {
+            warn!("failed to open {} for {}: {}", dest.display(), name, e);
+            return;
+        }
+    };
+    let fallback = tags::find_version_tag(&repo, name, version).map(|(_, commit)| {
+        (
+            commit,
+            package_dir.strip_prefix(dest).unwrap_or(Path::new("")),
+        )
+    });
+    let found = match tarball::compare(crate_file, &repo, fallback) {
+        Ok(found) => found,
+        Err(e) => {
+            warn!(
+                "failed to compare the tarball of {} with git: {:#}",
+                name, e
+            );
+            return;
+        }
+    };
+    if !found.is_empty() {
+        warn!(
+            "{} {} has {} published files that don't match git",
+            name,
+            version,
+            found.len()
+        );
+    }
+    if let Err(e) = store_discrepancies(conn, name, version, &found) {
+        warn!("failed to store discrepancies for {}: {}", name, e);
+    }
+}
+
+/// Replace the stored discrepancies of `name` with those found in `version`.
+fn store_discrepancies(
+    conn: &Connection,
+    name: &str,
+    version: &str,
+    found: &[tarball::Discrepancy],
+) -> rusqlite::Result<()> {
+    let tx = batch::Savepoint::new(conn)?;
+    tx.execute(
+        "DELETE FROM discrepancies WHERE crate_name = ?1",
+        params![name],
+    )?;
+    for discrepancy in found {
+        tx.execute(
+            "INSERT INTO discrepancies (crate_name, version, path, kind) VALUES (?1, ?2, ?3, ?4)",
+            params![name, version, discrepancy.path, discrepancy.kind],
+        )?;
+    }
+    tx.commit()
+}
+
+/// Replace the stored advisories for `name`. `None` means there was no lockfile to
+/// check, which leaves `advisory_count` NULL rather than zero.
+fn store_findings(
+    conn: &Connection,
+    name: &str,
+    findings: Option<&[advisories::Finding]>,
+) -> rusqlite::Result<()> {
+    let tx = batch::Savepoint::new(conn)?;
+    tx.execute(
+        "DELETE FROM vulnerabilities WHERE crate_name = ?1",
+        params![name],
+    )?;
+    for finding in findings.unwrap_or_default() {
+        tx.execute(
+            "INSERT OR IGNORE INTO vulnerabilities (crate_name, advisory_id, package, version)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![name, finding.id, finding.package, finding.version],
+        )?;
+    }
+    tx.execute(
+        "UPDATE crates SET advisory_count = ?2 WHERE name = ?1",
+        params![name, findings.map(|f| f.len())],
+    )?;
+    tx.commit()
+}
+
+/// The number of crates depending on `name`, from the database if the stored count is
+/// younger than `max_age`, otherwise from the API. `None` if it couldn't be fetched.
+fn reverse_dep_count(
+    conn: &Connection,
+    client: &SyncClient,
+    name: &str,
+    max_age: Duration,
+) -> Option<u64> {
+    let cached: Option<(Option<u64>, Option<u64>)> = conn
+        .query_row(
+            "SELECT reverse_dep_count, reverse_dep_fetched_at FROM crates WHERE name = ?1",
+            [name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .ok()
+        .flatten();
+    if let Some((Some(count), Some(fetched_at))) = cached
+        && unix_now().saturating_sub(fetched_at) < max_age.as_secs()
+    {
+        return Some(count);
+    }
+
+    let started = Instant::now();
+    match client.crate_reverse_dependency_count(name) {
+        Ok(count) => {
+            debug!(
+                "reverse dependencies for {} took {:?}",
+                name,
+                started.elapsed()
+            );
+            batch::try_write(name, || {
+                conn.execute(
+                    "INSERT INTO crates (name, repository, status, reverse_dep_count, reverse_dep_fetched_at)
+                     VALUES (?1, NULL, ?4, ?2, ?3)
+                     ON CONFLICT(name) DO UPDATE SET reverse_dep_count = excluded.reverse_dep_count,
+                                                     reverse_dep_fetched_at = excluded.reverse_dep_fetched_at",
+                    params![name, count, unix_now(), CrateStatus::Pending],
+                )
+            });
+            Some(count)
+        }
+        Err(e) => {
+            warn!("failed to fetch reverse dependencies for {}: {}", name, e);
+            None
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Set `name` to the failure `status`, recording the error and whatever numeric codes
+/// came with it in place of any earlier ones.
+fn record_failure(
+    conn: &Connection,
+    name: &str,
+    status: CrateStatus,
+    error: &str,
+    http_status: Option<u16>,
+    git_code: Option<(i32, u32)>,
+    compress: bool,
+) {
+    batch::try_write(name, || {
+        conn.execute(
+            "INSERT INTO crates (name, status, http_status, git_error_code, git_error_class)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(name) DO UPDATE SET
+                 status = excluded.status,
+                 http_status = excluded.http_status,
+                 git_error_code = excluded.git_error_code,
+                 git_error_class = excluded.git_error_class",
+            params![
+                name,
+                status,
+                http_status,
+                git_code.map(|(code, _)| code),
+                git_code.map(|(_, class)| class),
+            ],
+        )
+    });
+    batch::try_write(name, || {
+        texts::store(conn, name, texts::LAST_ERROR, Some(error), compress)
+    });
+}
+
+/// Run `f`, catching a panic and returning its message instead when `keep_going` is
+/// set (--keep-going-on-panic). Otherwise panics end the run as usual.
+fn isolate<T>(keep_going: bool, f: impl FnOnce() -> T) -> Result<T, String> {
+    if !keep_going {
+        return Ok(f());
+    }
+    std::panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panicked with a non-string payload".to_owned())
+    })
+}
+
+/// Record `name` as panicked. Savepoints roll themselves back as the panic unwinds,
+/// but a statement cut off outside one can leave a transaction open; outside
+/// --checkpoint-every there shouldn't be one, so it's rolled back first.
+fn record_panic(
+    conn: &Connection,
+    reporter: &console::Reporter,
+    args: &Args,
+    name: &str,
+    message: &str,
+) {
+    if args.checkpoint_every == 0 && !conn.is_autocommit() {
+        conn.execute_batch("ROLLBACK").ok();
+    }
+    reporter.report(name, CrateStatus::Panicked, Some(message));
+    record_failure(
+        conn,
+        name,
+        CrateStatus::Panicked,
+        message,
+        None,
+        None,
+        args.compress_db,
+    );
+}
+
+/// Log that crates.io refused the metadata request for `name` with a 403, with what
+/// to do about it, and keep a record of it in `api_bans`.
+fn record_ban(conn: &Connection, name: &str, err: &anyhow::Error) {
+    let message = format!("{:#}", err);
+    error!(
+        "crates.io refused the metadata request for {}: {}\n\
+         This usually means it has blocked this IP address or user agent for breaking its \
+         crawler policy (https://crates.io/policies#crawlers). Stopping now so the remaining \
+         crates aren't all recorded as metadata_error. Wait before running again, raise \
+         --delay-ms, make sure the user agent ({}) identifies you, and if it persists \
+         email help@crates.io.",
+        name, message, USER_AGENT
+    );
+    batch::try_write(name, || {
+        conn.execute(
+            "INSERT INTO api_bans (at, crate_name, message) VALUES (?1, ?2, ?3)",
+            params![unix_now(), name, message],
+        )
+    });
+}
+
+/// The HTTP status behind a failed metadata request, if it got as far as a response.
+fn http_status(err: &anyhow::Error) -> Option<u16> {
+    match err.downcast_ref::<crates_io_api::Error>()? {
+        crates_io_api::Error::Http(e) => e.status().map(|s| s.as_u16()),
+        crates_io_api::Error::NotFound(_) => Some(404),
+        crates_io_api::Error::PermissionDenied(_) => Some(403),
+        _ => None,
+    }
+}
+
+/// If crates.io now lists a different repository for the cloned crate `name` than the
+/// one it was cloned from, move the checkout over to it and record repo_moved. A failed
+/// move is only logged, so the next run tries again.
+fn follow_moved_repo(
+    conn: &Connection,
+    reporter: &console::Reporter,
+    opts: &CloneOptions,
+    name: &str,
+    resp: &CrateResponse,
+) {
+    let Some(new) = resp.crate_data.repository.as_deref() else {
+        return;
+    };
+    let stored: Option<(Option<String>, Option<String>, Option<bool>)> = conn
+        .query_row(
+            "SELECT repository, repo_dir, overridden FROM crates WHERE name = ?1",
+            [name],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .ok()
+        .flatten();
+    let Some((Some(old), Some(repo_dir), overridden)) = stored else {
+        return;
+    };
+    // The checkout may have been deleted since.
+    if overridden == Some(true)
+        || clone::normalize_url(&old) == clone::normalize_url(new)
+        || !Path::new(&repo_dir).is_dir()
+    {
+        return;
+    }
+
+    let moved = format!("{} -> {}", old, new);
+    match clone::relocate(Path::new(&repo_dir), new, opts) {
+        Ok(()) => {
+            reporter.report(name, CrateStatus::RepoMoved, Some(&moved));
+            batch::try_write(name, || {
+                conn.execute(
+                    "UPDATE crates SET repository = ?2, status = ?3, resolved_url = NULL WHERE name = ?1",
+                    params![name, new, CrateStatus::RepoMoved],
+                )
+            });
+        }
+        Err(e) => warn!(
+            "{} moved ({}), but fetching from there failed: {}",
+            name, moved, e
+        ),
+    }
+}
+
+/// Whether `name`'s metadata was last fetched more than `max_age` ago, or never.
+fn metadata_is_stale(conn: &Connection, name: &str, max_age: Duration) -> bool {
+    let fetched_at: Option<u64> = conn
+        .query_row(
+            "SELECT metadata_fetched_at FROM crates WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()
+        .ok()
+        .flatten()
+        .flatten();
+    fetched_at.is_none_or(|at| unix_now().saturating_sub(at) >= max_age.as_secs())
+}
+
+/// Upsert the descriptive fields of `name` and replace its categories. A new row starts
+/// out as `pending`; an existing row keeps its status.
+fn store_metadata(
+    conn: &Connection,
+    name: &str,
+    resp: &CrateResponse,
+    compress: bool,
+) -> rusqlite::Result<()> {
+    let data = &resp.crate_data;
+    let tx = batch::Savepoint::new(conn)?;
+    tx.execute(
+        "INSERT INTO crates (name, repository, status, downloads, max_version, updated_at,
+                             metadata_fetched_at)
+         VALUES (?1, ?2, ?7, ?3, ?4, ?5, ?6)
+         ON CONFLICT(name) DO UPDATE SET
+             downloads = excluded.downloads,
+             max_version = excluded.max_version,
+             updated_at = excluded.updated_at,
+             metadata_fetched_at = excluded.metadata_fetched_at,
+             http_status = NULL,
+             git_error_code = NULL,
+             git_error_class = NULL",
+        params![
+            name,
+            data.repository,
+            data.downloads,
+            data.max_version,
+            data.updated_at.timestamp(),
+            unix_now(),
+            CrateStatus::Pending,
+        ],
+    )?;
+    texts::store(
+        &tx,
+        name,
+        texts::DESCRIPTION,
+        data.description.as_deref(),
+        compress,
+    )?;
+    texts::store(&tx, name, texts::LAST_ERROR, None, compress)?;
+    tx.execute(
+        "DELETE FROM categories WHERE crate_name = ?1",
+        params![name],
+    )?;
+    for category in &resp.categories {
+        tx.execute(
+            "INSERT INTO categories (crate_name, slug) VALUES (?1, ?2)",
+            params![name, category.slug],
+        )?;
+    }
+    tx.commit()
+}
+
+/// Pack the checkout at `dest` and put it in `store` as `<dir>.tar.gz`, returning
+/// where it went.
+fn upload(store: &dyn objstore::ObjectStore, dest: &Path) -> Result<String> {
+    let dir_name = dest
+        .file_name()
+        .context("checkout has no directory name")?
+        .to_string_lossy();
+    let key = format!("{}.tar.gz", dir_name);
+    let tarball = dest.with_file_name(format!("{}.part", key));
+    let uploaded = objstore::pack(dest, &tarball)
+        .with_context(|| format!("could not pack {}", dest.display()))
+        .and_then(|()| store.put(&key, &tarball));
+    std::fs::remove_file(&tarball).ok();
+    uploaded.map(|()| store.url(&key))
+}
+
+/// Keep the whole of `resp` for the `clone` subcommand.
+fn store_response(conn: &Connection, name: &str, resp: &CrateResponse, compress: bool) {
+    let stored = serde_json::to_string(resp)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| {
+            Ok(texts::store(
+                conn,
+                name,
+                texts::METADATA,
+                Some(&json),
+                compress,
+            )?)
+        });
+    if let Err(e) = stored {
+        warn!("failed to store the response for {}: {:#}", name, e);
+    }
+}
+
+/// The crates `harvest` kept a response for.
+fn harvested_crates(conn: &Connection) -> rusqlite::Result<HashSet<String>> {
+    conn.prepare("SELECT crate_name FROM crate_texts WHERE field = ?1")?
+        .query_map([texts::METADATA], |row| row.get(0))?
+        .collect()
+}
+
+/// Remove everything stored about `name`.
+fn forget_crate(conn: &Connection, name: &str) -> rusqlite::Result<()> {
+    let tx = batch::Savepoint::new(conn)?;
+    for table in [
+        "categories",
+        "owners",
+        "vulnerabilities",
+        "crate_texts",
+        "versions",
+        "discrepancies",
+    ] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE crate_name = ?1"),
+            params![name],
+        )?;
+    }
+    tx.execute("DELETE FROM crates WHERE name = ?1", params![name])?;
+    tx.commit()
+}
+
+/// Replace the stored owners of `name` with `owners`.
+fn store_owners(conn: &Connection, name: &str, owners: &[User]) -> rusqlite::Result<()> {
+    let tx = batch::Savepoint::new(conn)?;
+    tx.execute("DELETE FROM owners WHERE crate_name = ?1", params![name])?;
+    for owner in owners {
+        tx.execute(
+            "INSERT INTO owners (crate_name, login, kind, name) VALUES (?1, ?2, ?3, ?4)",
+            params![name, owner.login, owner.kind, owner.name],
+        )?;
+    }
+    tx.commit()
+}
+
+/// Route log output by verbosity: warnings and errors to stderr, the rest to stdout.
+fn init_logging(args: &Args) {
+    let level = match (args.quiet, args.verbose) {
+        (0, 0) => Level::INFO,
+        (0, 1) => Level::DEBUG,
+        (0, _) => Level::TRACE,
+        (_, _) => Level::ERROR,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(
+            std::io::stderr
+                .with_max_level(Level::WARN)
+                .or_else(std::io::stdout),
+        )
+        .with_target(false)
+        .with_level(args.verbose > 0)
+        .without_time()
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// A one-commit repository at `dir` to clone from.
+    fn upstream(dir: &Path) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        let repo = git2::Repository::init(dir).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("bugbot", "bugbot@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "fixture", &tree, &[])
+            .unwrap();
+    }
+
+    fn status(conn: &Connection, name: &str) -> Option<CrateStatus> {
+        conn.query_row("SELECT status FROM crates WHERE name = ?1", [name], |row| {
+            row.get::<_, String>(0)
+        })
+        .optional()
+        .unwrap()
+        .and_then(|status| CrateStatus::parse(&status))
+    }
+
+    #[test]
+    fn takes_over_checkouts_whose_batch_never_committed() {
+        let dir = TempDir::new("main");
+        let url = dir.path().join("upstream").to_string_lossy().into_owned();
+        upstream(Path::new(&url));
+        let db = dir.path().join("bugbot.sqlite");
+        let dest = dir.path().join("repos/demo");
+        let opts = clone_options(&Args::parse_from(["bugbot9000"]));
+
+        // A run records the crate and clones it inside a batch, then dies without
+        // committing, like on a second Ctrl-C: the row is gone, the checkout isn't.
+        {
+            let conn = Connection::open(&db).unwrap();
+            schema::migrate(&conn).unwrap();
+            let batch = batch::Batch::begin(&conn).unwrap();
+            conn.execute(
+                "INSERT INTO crates (name, repository, status) VALUES ('demo', ?1, ?2)",
+                params![url, CrateStatus::Pending],
+            )
+            .unwrap();
+            clone::clone("demo", &url, &dest, &opts).unwrap();
+            conn.execute(
+                "UPDATE crates SET status = ?1 WHERE name = 'demo'",
+                [CrateStatus::Cloned],
+            )
+            .unwrap();
+            std::mem::forget(batch);
+        }
+        let conn = Connection::open(&db).unwrap();
+        assert_eq!(status(&conn, "demo"), None);
+        assert!(dest.join("Cargo.toml").is_file());
+
+        // The next run adopts it rather than skipping it for good.
+        let existing = existing_checkout(status(&conn, "demo"), &dest, &opts, false);
+        assert_eq!(existing, Existing::Adopt { ours: true });
+        assert!(clone::adopt(&dest, &url).is_ok());
+
+        // Anything recorded, or not there at all, is decided as before.
+        assert_eq!(
+            existing_checkout(Some(CrateStatus::Failed), &dest, &opts, false),
+            Existing::Skip
+        );
+        assert_eq!(
+            existing_checkout(Some(CrateStatus::Failed), &dest, &opts, true),
+            Existing::Adopt { ours: false }
+        );
+        assert_eq!(
+            existing_checkout(None, &dir.path().join("repos/other"), &opts, false),
+            Existing::Clone
+        );
+    }
+}
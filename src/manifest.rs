@@ -0,0 +1,162 @@
+//! Finding and reading the crate's own `Cargo.toml` inside a cloned repository.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories that never hold the crate's own manifest but can be huge.
+const SKIP_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// Don't descend further than this looking for manifests.
+const MAX_DEPTH: usize = 8;
+
+/// Find the `Cargo.toml` under `root` whose `[package] name` is `crate_name`.
+pub fn find_package(root: &Path, crate_name: &str) -> Option<PathBuf> {
+    let mut stack = vec![(root.to_path_buf(), 0)];
+    while let Some((dir, depth)) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+
+            // `file_type` doesn't follow symlinks, so symlinked directories are skipped
+            // and can't send us round in circles.
+            if file_type.is_dir() {
+                let hidden = file_name.starts_with('.');
+                if depth < MAX_DEPTH && !hidden && !SKIP_DIRS.contains(&file_name.as_ref()) {
+                    stack.push((path, depth + 1));
+                }
+            } else if file_name == "Cargo.toml"
+                && package_name(&path).as_deref() == Some(crate_name)
+            {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// The `[package] name` of the manifest at `path`, if it parses and has one.
+fn package_name(path: &Path) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    let manifest: toml::Value = toml::from_str(&text).ok()?;
+    manifest
+        .get("package")?
+        .get("name")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+/// Which table of a manifest a dependency was declared in.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DepKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+const DEP_TABLES: [(&str, DepKind); 3] = [
+    ("dependencies", DepKind::Normal),
+    ("dev-dependencies", DepKind::Dev),
+    ("build-dependencies", DepKind::Build),
+];
+
+/// The crates the manifest at `path` depends on, by their crates.io name (a renamed
+/// dependency's `package`), including target-specific ones.
+pub fn dependencies(path: &Path) -> Vec<(String, DepKind)> {
+    let Some(manifest) = fs::read_to_string(path)
+        .ok()
+        .and_then(|text| toml::from_str::<toml::Value>(&text).ok())
+    else {
+        return Vec::new();
+    };
+    let targets = manifest
+        .get("target")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values());
+
+    let mut deps = Vec::new();
+    for section in std::iter::once(&manifest).chain(targets) {
+        for (table, kind) in DEP_TABLES {
+            let Some(table) = section.get(table).and_then(toml::Value::as_table) else {
+                continue;
+            };
+            for (key, spec) in table {
+                let name = spec
+                    .get("package")
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or(key);
+                deps.push((name.to_owned(), kind));
+            }
+        }
+    }
+    deps.sort();
+    deps.dedup();
+    deps
+}
+
+/// The edition and `rust-version` a package targets.
+#[derive(Default)]
+pub struct RustTarget {
+    /// `edition`, which Cargo takes to be 2015 when it's missing.
+    pub edition: Option<String>,
+    pub rust_version: Option<String>,
+}
+
+/// The edition and `rust-version` of the manifest at `path`. Values inherited with
+/// `edition.workspace = true` are looked up in the nearest enclosing workspace
+/// manifest's `[workspace.package]`.
+pub fn rust_target(path: &Path) -> RustTarget {
+    let Some(package) = read_toml(path).and_then(|m| m.get("package").cloned()) else {
+        return RustTarget::default();
+    };
+    let field = |key: &str| match package.get(key)? {
+        toml::Value::String(s) => Some(s.clone()),
+        v if v.get("workspace").and_then(toml::Value::as_bool) == Some(true) => {
+            workspace_package_field(path, key)
+        }
+        _ => None,
+    };
+    RustTarget {
+        edition: field("edition").or_else(|| Some("2015".to_owned())),
+        rust_version: field("rust-version"),
+    }
+}
+
+/// `key` from `[workspace.package]` of the first manifest above `path` with one.
+fn workspace_package_field(path: &Path, key: &str) -> Option<String> {
+    path.parent()?
+        .ancestors()
+        .skip(1)
+        .take(MAX_DEPTH)
+        .filter_map(|dir| read_toml(&dir.join("Cargo.toml")))
+        .find_map(|manifest| {
+            manifest
+                .get("workspace")?
+                .get("package")?
+                .get(key)?
+                .as_str()
+                .map(str::to_owned)
+        })
+}
+
+fn read_toml(path: &Path) -> Option<toml::Value> {
+    toml::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// A `rust-version` such as `1.70` or `1.70.0` as `(major, minor, patch)`, for
+/// comparing.
+pub fn parse_rust_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim().split('.').map(str::parse::<u64>);
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    parts.next().is_none().then_some((major, minor, patch))
+}
@@ -0,0 +1,144 @@
+//! Where crate metadata comes from: the live API, responses recorded from it, or the
+//! responses `harvest` kept in the database.
+
+use anyhow::{Context, Result};
+use crates_io_api::{CrateResponse, SyncClient};
+use rusqlite::Connection;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// Something that can answer `GET /crates/{name}`.
+pub trait CrateMetadataSource {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse>;
+}
+
+impl CrateMetadataSource for SyncClient {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse> {
+        Ok(SyncClient::get_crate(self, name)?)
+    }
+}
+
+impl<S: CrateMetadataSource + ?Sized> CrateMetadataSource for &S {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse> {
+        (**self).get_crate(name)
+    }
+}
+
+/// How many times a throttled request is retried, and how long the first retry waits;
+/// each later one waits twice as long as the last.
+const THROTTLE_RETRIES: u32 = 5;
+const THROTTLE_WAIT: Duration = Duration::from_secs(30);
+
+/// Retries requests crates.io turned away with 429 Too Many Requests, backing off each
+/// time, so throttling slows the run down instead of failing crates.
+pub struct RetryThrottled<S> {
+    inner: S,
+}
+
+impl<S> RetryThrottled<S> {
+    pub fn new(inner: S) -> Self {
+        RetryThrottled { inner }
+    }
+}
+
+impl<S: CrateMetadataSource> CrateMetadataSource for RetryThrottled<S> {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse> {
+        let mut wait = THROTTLE_WAIT;
+        for _ in 0..THROTTLE_RETRIES {
+            match self.inner.get_crate(name) {
+                Err(e) if crate::http_status(&e) == Some(429) => {
+                    warn!(
+                        "crates.io is throttling us; retrying {} in {:?}",
+                        name, wait
+                    );
+                    thread::sleep(wait);
+                    wait *= 2;
+                }
+                result => return result,
+            }
+        }
+        self.inner.get_crate(name)
+    }
+}
+
+fn response_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.json"))
+}
+
+/// Passes requests through to another source and saves every successful response as
+/// `<dir>/<name>.json`, ready for [`Replay`].
+pub struct Recorder<S> {
+    inner: S,
+    dir: PathBuf,
+}
+
+impl<S> Recorder<S> {
+    pub fn new(inner: S, dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("could not create {}", dir.display()))?;
+        Ok(Recorder {
+            inner,
+            dir: dir.to_path_buf(),
+        })
+    }
+}
+
+impl<S: CrateMetadataSource> CrateMetadataSource for Recorder<S> {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse> {
+        let resp = self.inner.get_crate(name)?;
+        // A failed recording shouldn't fail the crate; the response itself is fine.
+        let path = response_path(&self.dir, name);
+        let written = serde_json::to_vec_pretty(&resp)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(&path, json)?));
+        if let Err(e) = written {
+            warn!("failed to record response to {}: {:#}", path.display(), e);
+        }
+        Ok(resp)
+    }
+}
+
+/// Serves responses saved by [`Recorder`] without touching the network. Crates that
+/// were never recorded fail like a metadata error would.
+pub struct Replay {
+    dir: PathBuf,
+}
+
+impl Replay {
+    pub fn new(dir: &Path) -> Self {
+        Replay {
+            dir: dir.to_path_buf(),
+        }
+    }
+}
+
+impl CrateMetadataSource for Replay {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse> {
+        let path = response_path(&self.dir, name);
+        let json = fs::read(&path)
+            .with_context(|| format!("no recorded response at {}", path.display()))?;
+        serde_json::from_slice(&json).with_context(|| format!("could not parse {}", path.display()))
+    }
+}
+
+/// Serves the responses the `harvest` subcommand kept in the database, for `clone`.
+pub struct Stored<'c> {
+    conn: &'c Connection,
+}
+
+impl<'c> Stored<'c> {
+    pub fn new(conn: &'c Connection) -> Self {
+        Stored { conn }
+    }
+}
+
+impl CrateMetadataSource for Stored<'_> {
+    fn get_crate(&self, name: &str) -> Result<CrateResponse> {
+        let json = crate::texts::load(self.conn, name, crate::texts::METADATA)?
+            .with_context(|| format!("no harvested metadata for {}", name))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("could not parse the harvested metadata of {}", name))
+    }
+}
@@ -0,0 +1,680 @@
+//! Walking the crates of a finished mirror, lazily, straight from its database.
+//!
+//! ```no_run
+//! use bugbot9000::mirror::{Filter, Mirror};
+//!
+//! let mirror = Mirror::open("bugbot.sqlite")?;
+//! let filter = Filter::new().category("parser-implementations").min_downloads(10_000);
+//! for entry in mirror.crates(&filter) {
+//!     let (record, checkout) = entry?;
+//!     println!("{} {} in {}", record.name, record.status, checkout);
+//! }
+//! # Ok::<(), bugbot9000::mirror::MirrorError>(())
+//! ```
+
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, OpenFlags, OptionalExtension, Row};
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::status::CrateStatus;
+
+/// Statuses of crates whose checkout is in the mirror, the default for [`Filter`].
+pub const CHECKED_OUT: [CrateStatus; 4] = CrateStatus::CHECKED_OUT;
+
+/// Rows fetched from the database at a time.
+const PAGE_SIZE: u32 = 256;
+
+/// The oldest database schema with every column [`CrateRecord`] reads.
+const MIN_SCHEMA_VERSION: u32 = 17;
+
+/// The schema that added `archive_url`; older mirrors have no uploaded checkouts.
+const ARCHIVE_URL_SCHEMA_VERSION: u32 = 24;
+
+/// Why a mirror couldn't be read, or why a crate in it has no checkout.
+#[derive(Debug, thiserror::Error)]
+pub enum MirrorError {
+    /// The database file couldn't be opened.
+    #[error("could not open {}: {source}", path.display())]
+    Open {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+    /// The file is an SQLite database, but not one bugbot9000 wrote.
+    #[error("{} is not a bugbot9000 database", path.display())]
+    NotAMirror { path: PathBuf },
+    /// The database was written by a bugbot9000 too old to have every column this
+    /// library reads; running a current bugbot9000 against it once upgrades it.
+    #[error(
+        "database schema is v{version}, but at least v{required} is needed; run bugbot9000 on it to upgrade"
+    )]
+    OldSchema { version: u32, required: u32 },
+    /// The crate wasn't in the crates.io index when the mirror was built, or no run has
+    /// finished reading the index yet.
+    #[error("{name} isn't in the crates.io index the mirror was built from")]
+    IndexError { name: String },
+    /// Fetching the crate's metadata from the crates.io API failed.
+    #[error("{}", api_message(name, *http_status))]
+    ApiError {
+        name: String,
+        http_status: Option<u16>,
+    },
+    /// The crate's repository couldn't be cloned, or not at the right commit.
+    #[error("{name}: {kind}")]
+    CloneError {
+        name: String,
+        kind: CloneErrorKind,
+        /// libgit2's raw error code, where the failure came from libgit2.
+        git_code: Option<i32>,
+    },
+    /// The crate was seen but deliberately not checked out: filtered, skipped, still
+    /// pending, or without a repository. `status` is as in [`CrateRecord::status`].
+    #[error("{name} has no checkout (status {status})")]
+    NotCheckedOut { name: String, status: String },
+    /// A query failed, e.g. because bugbot9000 held a lock on the database for too long.
+    #[error("database query failed: {0}")]
+    DbError(#[from] rusqlite::Error),
+}
+
+fn api_message(name: &str, http_status: Option<u16>) -> String {
+    match http_status {
+        Some(status) => format!("fetching metadata for {name} failed with HTTP {status}"),
+        None => format!("fetching metadata for {name} failed"),
+    }
+}
+
+/// What went wrong in a [`MirrorError::CloneError`], from the status bugbot9000
+/// recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CloneErrorKind {
+    /// The repository asked for credentials that didn't work.
+    Auth,
+    /// The repository's host didn't answer.
+    HostUnreachable,
+    /// The host's TLS certificate wasn't trusted.
+    Tls,
+    /// The clone worked but HEAD isn't the version tag's commit.
+    HeadTagMismatch,
+    /// Processing the crate panicked.
+    Panicked,
+    /// Anything else; the run's log or `last_error` has the details.
+    Other,
+}
+
+impl CloneErrorKind {
+    /// The kind of failure `status` records, if it's a clone failure.
+    fn from_status(status: CrateStatus) -> Option<Self> {
+        Some(match status {
+            CrateStatus::AuthRequired => CloneErrorKind::Auth,
+            CrateStatus::HostUnreachable => CloneErrorKind::HostUnreachable,
+            CrateStatus::TlsError => CloneErrorKind::Tls,
+            CrateStatus::HeadTagMismatch => CloneErrorKind::HeadTagMismatch,
+            CrateStatus::Panicked => CloneErrorKind::Panicked,
+            CrateStatus::Failed => CloneErrorKind::Other,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for CloneErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            CloneErrorKind::Auth => CrateStatus::AuthRequired,
+            CloneErrorKind::HostUnreachable => CrateStatus::HostUnreachable,
+            CloneErrorKind::Tls => CrateStatus::TlsError,
+            CloneErrorKind::HeadTagMismatch => CrateStatus::HeadTagMismatch,
+            CloneErrorKind::Panicked => CrateStatus::Panicked,
+            CloneErrorKind::Other => CrateStatus::Failed,
+        };
+        f.write_str(status.description())
+    }
+}
+
+/// What the database records about one crate.
+#[derive(Clone, Debug)]
+pub struct CrateRecord {
+    pub name: String,
+    pub repository: Option<String>,
+    /// One of the values listed by `bugbot9000 --list-statuses`.
+    pub status: String,
+    pub downloads: Option<u64>,
+    pub max_version: Option<String>,
+    pub edition: Option<String>,
+    pub rust_version: Option<String>,
+}
+
+impl CrateRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(CrateRecord {
+            name: row.get("name")?,
+            repository: row.get("repository")?,
+            status: row.get("status")?,
+            downloads: row.get("downloads")?,
+            max_version: row.get("max_version")?,
+            edition: row.get("edition")?,
+            rust_version: row.get("rust_version")?,
+        })
+    }
+}
+
+/// Where a crate's checkout is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Checkout {
+    /// A directory on disk.
+    Dir(PathBuf),
+    /// A `.tar.gz` of the checkout in the object store given to `--output-url`.
+    Archive(String),
+}
+
+impl fmt::Display for Checkout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Checkout::Dir(path) => write!(f, "{}", path.display()),
+            Checkout::Archive(url) => f.write_str(url),
+        }
+    }
+}
+
+/// Which crates [`Mirror::crates`] yields. Every condition given must hold.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    statuses: Vec<String>,
+    categories: Vec<String>,
+    min_downloads: Option<u64>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            statuses: CHECKED_OUT.map(String::from).to_vec(),
+            categories: Vec::new(),
+            min_downloads: None,
+        }
+    }
+}
+
+impl Filter {
+    /// Every crate with a checkout in the mirror.
+    pub fn new() -> Self {
+        Filter::default()
+    }
+
+    /// Only crates with one of these statuses, instead of [`CHECKED_OUT`]. Crates
+    /// without a checkout directory or archive are never yielded, whatever their status.
+    pub fn statuses<I, S>(mut self, statuses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.statuses = statuses.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only crates in the crates.io category with this slug. Given more than once, a
+    /// crate must be in all of them.
+    pub fn category(mut self, slug: impl Into<String>) -> Self {
+        self.categories.push(slug.into());
+        self
+    }
+
+    /// Only crates with at least this many all-time downloads.
+    pub fn min_downloads(mut self, downloads: u64) -> Self {
+        self.min_downloads = Some(downloads);
+        self
+    }
+
+    /// The `WHERE` clause and its parameters, after the `name > ?` keyset condition.
+    /// `archive_url` is the column's name, or `NULL` where the schema predates it.
+    fn sql(&self, archive_url: &str) -> (String, Vec<Value>) {
+        let mut clause = format!("(repo_dir IS NOT NULL OR {archive_url} IS NOT NULL)");
+        let mut params = Vec::new();
+        let marks = vec!["?"; self.statuses.len()].join(", ");
+        clause.push_str(&format!(" AND status IN ({marks})"));
+        params.extend(self.statuses.iter().cloned().map(Value::Text));
+        for slug in &self.categories {
+            clause.push_str(
+                " AND EXISTS (SELECT 1 FROM categories
+                              WHERE categories.crate_name = crates.name AND slug = ?)",
+            );
+            params.push(Value::Text(slug.clone()));
+        }
+        if let Some(min) = self.min_downloads {
+            clause.push_str(" AND downloads >= ?");
+            params.push(Value::Integer(min.try_into().unwrap_or(i64::MAX)));
+        }
+        (clause, params)
+    }
+}
+
+/// A mirror's database, opened read-only.
+pub struct Mirror {
+    conn: Connection,
+    /// What relative `repo_dir`s are relative to: the directory bugbot9000 ran in,
+    /// which is where it keeps the database.
+    root: PathBuf,
+    /// What to select as `archive_url`: the column, or `NULL` before schema v24.
+    archive_url: &'static str,
+}
+
+impl Mirror {
+    /// Open the `bugbot.sqlite` at `db`. A running bugbot9000 can keep writing to it
+    /// meanwhile.
+    pub fn open(db: impl AsRef<Path>) -> Result<Self, MirrorError> {
+        let db = db.as_ref();
+        let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(
+            |source| MirrorError::Open {
+                path: db.to_path_buf(),
+                source,
+            },
+        )?;
+        let not_a_mirror = || MirrorError::NotAMirror {
+            path: db.to_path_buf(),
+        };
+        // Anything that isn't a readable `meta` table with a numeric version means the
+        // file came from somewhere else.
+        let version: Option<String> = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'schema_version'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|_| not_a_mirror())?;
+        let version: u32 = version
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(not_a_mirror)?;
+        if version < MIN_SCHEMA_VERSION {
+            return Err(MirrorError::OldSchema {
+                version,
+                required: MIN_SCHEMA_VERSION,
+            });
+        }
+        let root = db.parent().unwrap_or(Path::new("")).to_path_buf();
+        let archive_url = if version >= ARCHIVE_URL_SCHEMA_VERSION {
+            "archive_url"
+        } else {
+            "NULL"
+        };
+        Ok(Mirror {
+            conn,
+            root,
+            archive_url,
+        })
+    }
+
+    /// Every crate matching `filter`, by name, with where its checkout is.
+    ///
+    /// Rows are read a page at a time as the iterator advances, so walking a whole
+    /// mirror never holds more than one page in memory; crates added while it runs may
+    /// or may not be seen.
+    pub fn crates(&self, filter: &Filter) -> Crates<'_> {
+        let archive_url = self.archive_url;
+        let (clause, params) = filter.sql(archive_url);
+        Crates {
+            mirror: self,
+            sql: format!(
+                "SELECT name, repository, status, downloads, max_version, edition,
+                        rust_version, repo_dir, {archive_url} AS archive_url
+                 FROM crates
+                 WHERE name > ? AND {clause}
+                 ORDER BY name
+                 LIMIT {PAGE_SIZE}"
+            ),
+            params,
+            after: String::new(),
+            page: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// The crate called `name` and where its checkout is, or why it hasn't got one.
+    pub fn get(&self, name: &str) -> Result<(CrateRecord, Checkout), MirrorError> {
+        let row = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT name, repository, status, downloads, max_version, edition,
+                            rust_version, repo_dir, {} AS archive_url, http_status,
+                            git_error_code
+                     FROM crates
+                     WHERE name = ?1",
+                    self.archive_url
+                ),
+                [name],
+                |row| {
+                    Ok((
+                        CrateRecord::from_row(row)?,
+                        self.checkout(row)?,
+                        row.get::<_, Option<u16>>("http_status")?,
+                        row.get::<_, Option<i32>>("git_error_code")?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((record, checkout, http_status, git_code)) = row else {
+            return Err(MirrorError::IndexError {
+                name: name.to_owned(),
+            });
+        };
+        let status = CrateStatus::parse(&record.status);
+        let failure = status.and_then(|status| match status {
+            CrateStatus::MetadataError => Some(MirrorError::ApiError {
+                name: record.name.clone(),
+                http_status,
+            }),
+            status => CloneErrorKind::from_status(status).map(|kind| MirrorError::CloneError {
+                name: record.name.clone(),
+                kind,
+                git_code,
+            }),
+        });
+        match (failure, checkout) {
+            (Some(failure), _) => Err(failure),
+            (None, Some(checkout)) => Ok((record, checkout)),
+            (None, None) => Err(MirrorError::NotCheckedOut {
+                name: record.name,
+                status: record.status,
+            }),
+        }
+    }
+
+    /// The checkout a row with `repo_dir` and `archive_url` columns points at; a
+    /// directory wins over an archive.
+    fn checkout(&self, row: &Row) -> rusqlite::Result<Option<Checkout>> {
+        if let Some(repo_dir) = row.get::<_, Option<String>>("repo_dir")? {
+            return Ok(Some(Checkout::Dir(self.root.join(repo_dir))));
+        }
+        Ok(row
+            .get::<_, Option<String>>("archive_url")?
+            .map(Checkout::Archive))
+    }
+}
+
+/// The iterator returned by [`Mirror::crates`].
+pub struct Crates<'a> {
+    mirror: &'a Mirror,
+    sql: String,
+    params: Vec<Value>,
+    /// Name of the last crate fetched; the next page starts after it.
+    after: String,
+    page: VecDeque<(CrateRecord, Checkout)>,
+    done: bool,
+}
+
+impl Crates<'_> {
+    fn fetch_page(&mut self) -> Result<(), MirrorError> {
+        let mut stmt = self.mirror.conn.prepare_cached(&self.sql)?;
+        let params = std::iter::once(Value::Text(self.after.clone())).chain(self.params.clone());
+        let rows = stmt.query_map(params_from_iter(params), |row| {
+            Ok((CrateRecord::from_row(row)?, self.mirror.checkout(row)?))
+        })?;
+        // The `WHERE` clause already left out crates without a checkout.
+        for row in rows {
+            if let (record, Some(checkout)) = row? {
+                self.page.push_back((record, checkout));
+            }
+        }
+        self.done = self.page.len() < PAGE_SIZE as usize;
+        if let Some((last, _)) = self.page.back() {
+            self.after = last.name.clone();
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Crates<'_> {
+    type Item = Result<(CrateRecord, Checkout), MirrorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty()
+            && !self.done
+            && let Err(e) = self.fetch_page()
+        {
+            self.done = true;
+            return Some(Err(e));
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use rusqlite::params;
+
+    /// More than two pages' worth, so iteration has to fetch several.
+    const CRATES: usize = PAGE_SIZE as usize * 2 + 17;
+
+    /// A mirror database at `dir/bugbot.sqlite` with [`CRATES`] crates, `crate-0000`
+    /// onwards. Every third one failed to clone; the rest are checked out under
+    /// `repos/`, those divisible by five are in the `parsing` category, and crate `i`
+    /// has `i * 10` downloads.
+    fn fixture(dir: &Path, version: u32) -> PathBuf {
+        let db = dir.join("bugbot.sqlite");
+        let conn = Connection::open(&db).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             CREATE TABLE crates (
+                 name TEXT PRIMARY KEY, repository TEXT, status TEXT NOT NULL,
+                 downloads INTEGER, max_version TEXT, edition TEXT, rust_version TEXT,
+                 repo_dir TEXT, archive_url TEXT, http_status INTEGER,
+                 git_error_code INTEGER
+             );
+             CREATE TABLE categories (crate_name TEXT NOT NULL, slug TEXT NOT NULL);",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO meta VALUES ('schema_version', ?1)",
+            [version.to_string()],
+        )
+        .unwrap();
+        for i in 0..CRATES {
+            let name = format!("crate-{i:04}");
+            let (status, repo_dir) = if i % 3 == 0 {
+                (CrateStatus::Failed, None)
+            } else {
+                let status = CHECKED_OUT[i % CHECKED_OUT.len()];
+                (status, Some(format!("repos/{name}")))
+            };
+            conn.execute(
+                "INSERT INTO crates VALUES (?1, ?2, ?3, ?4, '1.0.0', '2021', NULL, ?5, NULL, NULL, NULL)",
+                params![
+                    name,
+                    format!("https://github.com/example/{name}"),
+                    status,
+                    i as i64 * 10,
+                    repo_dir
+                ],
+            )
+            .unwrap();
+            if i % 5 == 0 {
+                conn.execute(
+                    "INSERT INTO categories VALUES (?1, 'parsing')",
+                    params![name],
+                )
+                .unwrap();
+            }
+        }
+        db
+    }
+
+    fn names(mirror: &Mirror, filter: &Filter) -> Vec<String> {
+        mirror
+            .crates(filter)
+            .map(|entry| entry.unwrap().0.name)
+            .collect()
+    }
+
+    #[test]
+    fn walks_every_checkout_in_order() {
+        let dir = TempDir::new("mirror");
+        let mirror = Mirror::open(fixture(dir.path(), 24)).unwrap();
+
+        let entries: Vec<(CrateRecord, Checkout)> =
+            mirror.crates(&Filter::new()).map(Result::unwrap).collect();
+        let expected: Vec<String> = (0..CRATES)
+            .filter(|i| i % 3 != 0)
+            .map(|i| format!("crate-{i:04}"))
+            .collect();
+        assert_eq!(
+            entries.iter().map(|(r, _)| &r.name).collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>()
+        );
+        for (record, checkout) in &entries {
+            assert_eq!(
+                checkout,
+                &Checkout::Dir(dir.path().join("repos").join(&record.name))
+            );
+            assert_ne!(record.status, "failed");
+            assert_eq!(record.max_version.as_deref(), Some("1.0.0"));
+        }
+    }
+
+    #[test]
+    fn filters_by_status_category_and_downloads() {
+        let dir = TempDir::new("mirror");
+        let mirror = Mirror::open(fixture(dir.path(), 24)).unwrap();
+
+        let filter = Filter::new().statuses([CrateStatus::Cloned]);
+        let cloned = names(&mirror, &filter);
+        assert!(!cloned.is_empty());
+        for entry in mirror.crates(&filter) {
+            assert_eq!(entry.unwrap().0.status, "cloned");
+        }
+
+        let filter = Filter::new().category("parsing").min_downloads(5_000);
+        let expected: Vec<String> = (500..CRATES)
+            .filter(|i| i % 3 != 0 && i % 5 == 0)
+            .map(|i| format!("crate-{i:04}"))
+            .collect();
+        assert_eq!(names(&mirror, &filter), expected);
+
+        // Crates without a checkout are left out whatever their status.
+        let failed = Filter::new().statuses([CrateStatus::Failed]);
+        assert!(names(&mirror, &failed).is_empty());
+        assert!(names(&mirror, &Filter::new().category("nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn says_why_a_crate_has_no_checkout() {
+        let dir = TempDir::new("mirror");
+        let db = fixture(dir.path(), 24);
+        let conn = Connection::open(&db).unwrap();
+        conn.execute_batch(
+            "UPDATE crates SET status = 'metadata_error', http_status = 503
+             WHERE name = 'crate-0000';
+             UPDATE crates SET status = 'auth_required', git_error_code = -16
+             WHERE name = 'crate-0003';
+             UPDATE crates SET git_error_code = -1 WHERE name = 'crate-0006';
+             UPDATE crates SET status = 'no_repo', repository = NULL
+             WHERE name = 'crate-0009';",
+        )
+        .unwrap();
+        let mirror = Mirror::open(&db).unwrap();
+
+        let (record, checkout) = mirror.get("crate-0001").unwrap();
+        assert_eq!(record.name, "crate-0001");
+        assert_eq!(checkout, Checkout::Dir(dir.path().join("repos/crate-0001")));
+
+        assert!(matches!(
+            mirror.get("crate-0000"),
+            Err(MirrorError::ApiError { name, http_status: Some(503) }) if name == "crate-0000"
+        ));
+        assert!(matches!(
+            mirror.get("crate-0003"),
+            Err(MirrorError::CloneError {
+                kind: CloneErrorKind::Auth,
+                git_code: Some(-16),
+                ..
+            })
+        ));
+        assert!(matches!(
+            mirror.get("crate-0006"),
+            Err(MirrorError::CloneError {
+                kind: CloneErrorKind::Other,
+                git_code: Some(-1),
+                ..
+            })
+        ));
+        assert!(matches!(
+            mirror.get("crate-0009"),
+            Err(MirrorError::NotCheckedOut { status, .. }) if status == "no_repo"
+        ));
+        let missing = mirror.get("not-a-crate").unwrap_err();
+        assert!(matches!(missing, MirrorError::IndexError { .. }));
+        assert_eq!(
+            missing.to_string(),
+            "not-a-crate isn't in the crates.io index the mirror was built from"
+        );
+    }
+
+    #[test]
+    fn finds_uploaded_checkouts() {
+        let dir = TempDir::new("mirror");
+        let db = fixture(dir.path(), 24);
+        let url = "s3://bucket/crate-0001.tar.gz";
+        Connection::open(&db)
+            .unwrap()
+            .execute(
+                "UPDATE crates SET repo_dir = NULL, archive_url = ?1 WHERE name = 'crate-0001'",
+                [url],
+            )
+            .unwrap();
+        let mirror = Mirror::open(&db).unwrap();
+
+        let (_, checkout) = mirror.get("crate-0001").unwrap();
+        assert_eq!(checkout, Checkout::Archive(url.to_owned()));
+        assert_eq!(checkout.to_string(), url);
+        let first = mirror.crates(&Filter::new()).next().unwrap().unwrap();
+        assert_eq!(first.0.name, "crate-0001");
+        assert_eq!(first.1, Checkout::Archive(url.to_owned()));
+    }
+
+    #[test]
+    fn reads_schemas_without_archive_url() {
+        let dir = TempDir::new("mirror");
+        let db = fixture(dir.path(), ARCHIVE_URL_SCHEMA_VERSION - 1);
+        let conn = Connection::open(&db).unwrap();
+        conn.execute_batch(
+            "ALTER TABLE crates DROP COLUMN archive_url;
+             UPDATE crates SET repo_dir = NULL WHERE name = 'crate-0001';",
+        )
+        .unwrap();
+        let mirror = Mirror::open(&db).unwrap();
+
+        assert!(matches!(
+            mirror.get("crate-0001"),
+            Err(MirrorError::NotCheckedOut { .. })
+        ));
+        assert_eq!(names(&mirror, &Filter::new())[0], "crate-0002");
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_read() {
+        let dir = TempDir::new("mirror");
+        let old = Mirror::open(fixture(dir.path(), MIN_SCHEMA_VERSION - 1));
+        assert!(matches!(
+            old,
+            Err(MirrorError::OldSchema { version, required: MIN_SCHEMA_VERSION })
+                if version == MIN_SCHEMA_VERSION - 1
+        ));
+
+        let other = dir.path().join("other.sqlite");
+        Connection::open(&other)
+            .unwrap()
+            .execute_batch("CREATE TABLE things (id INTEGER)")
+            .unwrap();
+        assert!(matches!(
+            Mirror::open(&other),
+            Err(MirrorError::NotAMirror { .. })
+        ));
+
+        assert!(matches!(
+            Mirror::open(dir.path().join("missing.sqlite")),
+            Err(MirrorError::Open { .. })
+        ));
+    }
+}
@@ -0,0 +1,262 @@
+//! Where finished checkouts go with `--output-url`: packed into a `.tar.gz` and put in
+//! an object store (S3, GCS, Azure, or a local directory).
+//!
+//! Git needs a real filesystem, so every clone is still made under `--output`; once the
+//! crate is done its checkout is packed, uploaded and deleted, and only one checkout's
+//! worth of disk is needed at a time.
+
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use object_store::WriteMultipart;
+use reqwest::Url;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// How much of a tarball is read at a time while uploading it.
+const READ_SIZE: usize = 1024 * 1024;
+
+/// Parts of a multipart upload allowed in flight at once, each `WriteMultipart`'s
+/// 5 MiB chunk size.
+const PARTS_IN_FLIGHT: usize = 4;
+
+pub trait ObjectStore {
+    /// Store the file at `path` as `key`.
+    fn put(&self, key: &str, path: &Path) -> Result<()>;
+
+    /// Where `key` ends up, for the `archive_url` column.
+    fn url(&self, key: &str) -> String;
+}
+
+/// Open the store `--output-url` names: `file://` URLs and plain paths are a
+/// [`LocalDir`], anything else goes through the `object_store` crate, which takes
+/// credentials from the usual environment variables (`AWS_ACCESS_KEY_ID`,
+/// `GOOGLE_SERVICE_ACCOUNT`, ...).
+pub fn open(url: &str) -> Result<Box<dyn ObjectStore>> {
+    match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "file" => {
+            let dir = parsed
+                .to_file_path()
+                .map_err(|()| anyhow::anyhow!("{} isn't a local path", url))?;
+            Ok(Box::new(LocalDir { dir }))
+        }
+        Ok(parsed) => Ok(Box::new(Remote::new(&parsed)?)),
+        // No scheme at all: a path.
+        Err(_) => Ok(Box::new(LocalDir {
+            dir: PathBuf::from(url),
+        })),
+    }
+}
+
+/// Pack the checkout at `dir` into a gzipped tarball at `tarball`, with everything
+/// under a top-level directory of the checkout's own name. Symlinks are stored as
+/// links, not followed.
+pub fn pack(dir: &Path, tarball: &Path) -> io::Result<()> {
+    let root = dir.file_name().unwrap_or(dir.as_os_str());
+    let encoder = GzEncoder::new(File::create(tarball)?, Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(root, dir)?;
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// A directory on a local or mounted filesystem.
+pub struct LocalDir {
+    dir: PathBuf,
+}
+
+impl ObjectStore for LocalDir {
+    fn put(&self, key: &str, path: &Path) -> Result<()> {
+        let dest = self.dir.join(key);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("could not create {}", parent.display()))?;
+        }
+        // Copied under a temporary name so a cut-off copy never looks complete.
+        let partial = dest.with_extension("part");
+        fs::copy(path, &partial)
+            .and_then(|_| fs::rename(&partial, &dest))
+            .with_context(|| format!("could not write {}", dest.display()))?;
+        Ok(())
+    }
+
+    fn url(&self, key: &str) -> String {
+        self.dir.join(key).to_string_lossy().into_owned()
+    }
+}
+
+/// A bucket or container reached through the `object_store` crate. Its API is async,
+/// so each upload runs to completion on a private single-threaded runtime.
+pub struct Remote {
+    store: Box<dyn object_store::ObjectStore>,
+    prefix: object_store::path::Path,
+    base: String,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Remote {
+    fn new(url: &Url) -> Result<Self> {
+        let (store, prefix) = object_store::parse_url(url)
+            .with_context(|| format!("unsupported --output-url {}", url))?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("could not start the upload runtime")?;
+        Ok(Remote {
+            store,
+            prefix,
+            base: url.as_str().trim_end_matches('/').to_owned(),
+            runtime,
+        })
+    }
+}
+
+impl ObjectStore for Remote {
+    /// Streamed as a multipart upload, so a large checkout is never in memory whole.
+    fn put(&self, key: &str, path: &Path) -> Result<()> {
+        let mut file =
+            File::open(path).with_context(|| format!("could not read {}", path.display()))?;
+        let location = key
+            .split('/')
+            .fold(self.prefix.clone(), |location, part| location.child(part));
+        self.runtime
+            .block_on(async {
+                let mut upload = WriteMultipart::new(self.store.put_multipart(&location).await?);
+                let mut buf = vec![0; READ_SIZE];
+                loop {
+                    let read = match file.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(e) => {
+                            upload.abort().await.ok();
+                            return Err(anyhow::Error::new(e)
+                                .context(format!("could not read {}", path.display())));
+                        }
+                    };
+                    if let Err(e) = upload.wait_for_capacity(PARTS_IN_FLIGHT).await {
+                        upload.abort().await.ok();
+                        return Err(e.into());
+                    }
+                    upload.write(&buf[..read]);
+                }
+                upload.finish().await?;
+                anyhow::Ok(())
+            })
+            .with_context(|| format!("could not upload {}", self.url(key)))
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use flate2::read::GzDecoder;
+    use std::collections::BTreeMap;
+
+    /// A small checkout at `dir/<name>`, with a nested file and, on Unix, a symlink.
+    fn checkout(dir: &Path, name: &str) -> PathBuf {
+        let root = dir.join(name);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn demo() {}\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("src/lib.rs", root.join("link.rs")).unwrap();
+        root
+    }
+
+    /// Every entry in the tarball at `path`: file contents, or `-> target` for links.
+    fn entries(path: &Path) -> BTreeMap<String, String> {
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(path).unwrap()));
+        let mut entries = BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            let value = match entry.header().entry_type() {
+                tar::EntryType::Symlink => {
+                    format!("-> {}", entry.link_name().unwrap().unwrap().display())
+                }
+                tar::EntryType::Directory => continue,
+                _ => {
+                    let mut text = String::new();
+                    entry.read_to_string(&mut text).unwrap();
+                    text
+                }
+            };
+            entries.insert(name, value);
+        }
+        entries
+    }
+
+    #[test]
+    fn packs_a_checkout_under_its_own_name() {
+        let dir = TempDir::new("objstore");
+        let root = checkout(dir.path(), "demo-1.0.0");
+        let tarball = dir.path().join("demo.tar.gz");
+        pack(&root, &tarball).unwrap();
+
+        let expected: BTreeMap<String, String> = [
+            ("demo-1.0.0/Cargo.toml", "[package]\nname = \"demo\"\n"),
+            #[cfg(unix)]
+            ("demo-1.0.0/link.rs", "-> src/lib.rs"),
+            ("demo-1.0.0/src/lib.rs", "pub fn demo() {}\n"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+        assert_eq!(entries(&tarball), expected);
+    }
+
+    #[test]
+    fn local_dir_copies_into_nested_keys() {
+        let dir = TempDir::new("objstore");
+        let source = dir.path().join("source.tar.gz");
+        fs::write(&source, b"tarball").unwrap();
+
+        let store = open(dir.path().join("store").to_str().unwrap()).unwrap();
+        store.put("a/b/demo.tar.gz", &source).unwrap();
+        let dest = dir.path().join("store/a/b/demo.tar.gz");
+        assert_eq!(fs::read(&dest).unwrap(), b"tarball");
+        assert!(!dest.with_extension("part").exists());
+        assert_eq!(store.url("a/b/demo.tar.gz"), dest.to_string_lossy());
+
+        let url = format!("file://{}", dir.path().join("by-url").display());
+        let store = open(&url).unwrap();
+        store.put("demo.tar.gz", &source).unwrap();
+        assert!(dir.path().join("by-url/demo.tar.gz").is_file());
+    }
+
+    #[test]
+    fn remote_streams_files_bigger_than_a_part() {
+        let dir = TempDir::new("objstore");
+        let source = dir.path().join("big.tar.gz");
+        // Two and a bit 5 MiB parts, and not a multiple of the read size either.
+        let bytes: Vec<u8> = (0..11 * 1024 * 1024 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(&source, &bytes).unwrap();
+
+        let store = Remote::new(&Url::parse("memory:///mirror").unwrap()).unwrap();
+        store.put("crates/big.tar.gz", &source).unwrap();
+        assert_eq!(
+            store.url("crates/big.tar.gz"),
+            "memory:///mirror/crates/big.tar.gz"
+        );
+
+        let location = object_store::path::Path::from("mirror/crates/big.tar.gz");
+        let stored = store
+            .runtime
+            .block_on(async { store.store.get(&location).await?.bytes().await })
+            .unwrap();
+        assert_eq!(stored.as_ref(), bytes.as_slice());
+
+        assert!(store
+            .put("missing.tar.gz", &dir.path().join("missing"))
+            .is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! Reading the `--repo-url-overrides` file: repository URLs to use instead of the ones
+//! crates.io lists.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Crate name to repository URL, from a TOML table of strings or, if the file name
+/// ends in `.csv`, from `name,url` lines (blank lines and `#` comments skipped).
+pub fn load(path: &Path) -> Result<HashMap<String, String>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
+    let csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let overrides = if csv {
+        parse_csv(&text)
+    } else {
+        toml::from_str(&text).map_err(anyhow::Error::from)
+    };
+    overrides.with_context(|| format!("invalid overrides file {}", path.display()))
+}
+
+fn parse_csv(text: &str) -> Result<HashMap<String, String>> {
+    let mut overrides = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, url)) = line.split_once(',') else {
+            bail!("line {}: expected `name,url`", n + 1);
+        };
+        overrides.insert(name.trim().to_owned(), url.trim().to_owned());
+    }
+    Ok(overrides)
+}
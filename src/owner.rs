@@ -0,0 +1,34 @@
+//! Listing the crates one crates.io user owns, for `--owner`.
+
+use anyhow::{Context, Result};
+use crates_io_api::{CratesQuery, SyncClient};
+use tracing::debug;
+
+/// The most crates.io will return per page.
+const PAGE_SIZE: u64 = 100;
+
+/// Names of every crate `login` owns, one rate-limited request per page.
+pub fn owned_crates(client: &SyncClient, login: &str) -> Result<Vec<String>> {
+    let user = client
+        .user(login)
+        .with_context(|| format!("could not look up crates.io user {}", login))?;
+
+    let mut names = Vec::new();
+    for page in 1.. {
+        let mut query = CratesQuery::builder()
+            .user_id(user.id)
+            .page_size(PAGE_SIZE)
+            .build();
+        query.set_page(page);
+        let resp = client
+            .crates(query)
+            .with_context(|| format!("could not list crates owned by {}", login))?;
+        debug!("page {} of {}'s crates: {}", page, login, resp.crates.len());
+        let done = resp.crates.len() < PAGE_SIZE as usize;
+        names.extend(resp.crates.into_iter().map(|krate| krate.name));
+        if done || names.len() as u64 >= resp.meta.total {
+            break;
+        }
+    }
+    Ok(names)
+}
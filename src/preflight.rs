@@ -0,0 +1,27 @@
+//! Checking, before a long run, that the network can reach what the run needs.
+
+use anyhow::{ensure, Context, Result};
+use crates_io_api::SyncClient;
+use git2::{Direction, Remote};
+use tracing::info;
+
+/// A repository that always exists, used to prove git can get out.
+pub const DEFAULT_PROBE_REPO: &str = "https://github.com/rust-lang/cfg-if";
+
+/// Make one API request and list the refs of `probe_repo`, failing with a message that
+/// says which of the two can't be reached.
+pub fn check(client: &SyncClient, probe_repo: &str) -> Result<()> {
+    client
+        .get_crate("cfg-if")
+        .context("can't reach the crates.io API")?;
+    info!("✓ crates.io API is reachable");
+
+    let mut remote = Remote::create_detached(probe_repo)?;
+    remote
+        .connect(Direction::Fetch)
+        .with_context(|| format!("can't reach {} with git", probe_repo))?;
+    let refs = remote.list()?.len();
+    ensure!(refs > 0, "{} has no refs", probe_repo);
+    info!("✓ {} is reachable ({} refs)", probe_repo, refs);
+    Ok(())
+}
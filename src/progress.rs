@@ -0,0 +1,72 @@
+//! Live per-crate events on a Unix socket, for `--progress-socket`.
+//!
+//! Every client that connects gets one JSON object per line for each crate finished
+//! from then on. Nothing here ever blocks the run: a client that isn't keeping up
+//! misses events, and one whose connection breaks is dropped. So is one with room
+//! for only part of an event, since the rest of its stream would be garbled.
+
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+pub struct ProgressSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<UnixStream>,
+}
+
+impl ProgressSocket {
+    /// Listen on `path`, replacing a socket left behind by an earlier run.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(ProgressSocket {
+            path: path.to_path_buf(),
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    /// Send `line` (without its newline) to every connected client.
+    pub fn send(&mut self, line: &str) {
+        self.accept();
+        let line = format!("{}\n", line);
+        self.clients
+            .retain_mut(|client| match client.write(line.as_bytes()) {
+                Ok(n) if n == line.len() => true,
+                // Half an event would garble the stream, so a short write ends the client.
+                Ok(_) => false,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    debug!("progress client is behind; dropping an event");
+                    true
+                }
+                Err(_) => false,
+            });
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((client, _)) => match client.set_nonblocking(true) {
+                    Ok(()) => self.clients.push(client),
+                    Err(e) => warn!("failed to set up progress client: {}", e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("failed to accept progress client: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ProgressSocket {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
@@ -0,0 +1,86 @@
+//! Comparing the index against another bugbot database, for `--diff-against`.
+
+use anyhow::{Context, Result};
+use crates_index::Crate;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// What a reference database knew about one crate.
+struct Entry {
+    repository: Option<String>,
+    max_version: Option<String>,
+}
+
+/// How a crate differs from its reference entry.
+pub enum Change {
+    New,
+    Version { from: String, to: String },
+}
+
+pub struct Reference {
+    crates: HashMap<String, Entry>,
+}
+
+impl Reference {
+    /// Read every crate from the database at `path`, which may be from any schema
+    /// version; ones that predate `max_version` can only tell new crates apart.
+    pub fn load(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("could not open {}", path.display()))?;
+        let has_version: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('crates') WHERE name = 'max_version'",
+            [],
+            |row| row.get(0),
+        )?;
+        let sql = if has_version {
+            "SELECT name, repository, max_version FROM crates"
+        } else {
+            "SELECT name, repository, NULL FROM crates"
+        };
+        let mut stmt = conn.prepare(sql)?;
+        let crates = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    Entry {
+                        repository: row.get(1)?,
+                        max_version: row.get(2)?,
+                    },
+                ))
+            })?
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("could not read crates from {}", path.display()))?;
+        Ok(Reference { crates })
+    }
+
+    /// How `krate` changed since the reference, or `None` if it didn't. Versions are
+    /// compared by the newest unyanked release in the index, which is what crates.io
+    /// reports as `max_version`.
+    pub fn change(&self, krate: &Crate) -> Option<Change> {
+        let Some(entry) = self.crates.get(krate.name()) else {
+            return Some(Change::New);
+        };
+        let from = entry.max_version.as_deref()?;
+        let to = newest_version(krate)?;
+        (from != to).then(|| Change::Version {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        })
+    }
+
+    /// The repository URL the reference recorded for `name`.
+    pub fn repository(&self, name: &str) -> Option<&str> {
+        self.crates.get(name)?.repository.as_deref()
+    }
+}
+
+fn newest_version(krate: &Crate) -> Option<&str> {
+    krate
+        .versions()
+        .iter()
+        .filter(|v| !v.is_yanked())
+        .filter_map(|v| Some((semver::Version::parse(v.version()).ok()?, v.version())))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, v)| v)
+}
@@ -0,0 +1,200 @@
+//! The `report` subcommand: a single self-contained HTML page summarizing the database.
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tera::Tera;
+
+use crate::repos::SharedRepos;
+use crate::status::CrateStatus;
+use crate::{clone, scan};
+
+const TEMPLATE: &str = include_str!("../templates/report.html");
+
+/// Pie slice colours, reused in order when there are more statuses than colours.
+const PALETTE: [&str; 10] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+    "#9c755f", "#bab0ac",
+];
+
+/// Rows shown in each of the ranked tables.
+const TOP: usize = 20;
+
+#[derive(Serialize)]
+struct Report {
+    total: u64,
+    statuses: Vec<Slice>,
+    forges: Vec<Count>,
+    repos: Vec<Repo>,
+    failures: Vec<Count>,
+    crates: Vec<Row>,
+}
+
+/// One status in the pie, spanning `start`..`end` percent of it.
+#[derive(Serialize)]
+struct Slice {
+    status: String,
+    count: u64,
+    color: &'static str,
+    start: f64,
+    end: f64,
+}
+
+#[derive(Serialize)]
+struct Count {
+    label: String,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct Repo {
+    dir: String,
+    crates: u64,
+    #[serde(skip)]
+    bytes: u64,
+    /// `bytes` in MiB, to one decimal place.
+    mib: String,
+}
+
+#[derive(Serialize)]
+struct Row {
+    name: String,
+    status: String,
+    repository: Option<String>,
+    max_version: Option<String>,
+    downloads: Option<u64>,
+}
+
+/// Render the report for `bugbot.sqlite` and the clones under `output` to `path`.
+///
+/// Repository sizes are measured on disk while the report is generated, so this walks
+/// every checkout once.
+pub fn write(output: &Path, path: &Path) -> Result<()> {
+    let conn = Connection::open_with_flags("bugbot.sqlite", OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .context("failed to open bugbot.sqlite")?;
+    let report = Report {
+        total: conn.query_row("SELECT COUNT(*) FROM crates", [], |row| row.get(0))?,
+        statuses: statuses(&conn)?,
+        forges: forges(&conn)?,
+        repos: largest_repos(&conn, output)?,
+        failures: failures(&conn)?,
+        crates: crates(&conn)?,
+    };
+
+    let context = tera::Context::from_serialize(&report)?;
+    let html = Tera::one_off(TEMPLATE, &context, true).context("failed to render report")?;
+    fs::write(path, html).with_context(|| format!("could not write {}", path.display()))
+}
+
+fn statuses(conn: &Connection) -> rusqlite::Result<Vec<Slice>> {
+    let counts: Vec<(String, u64)> = conn
+        .prepare(
+            "SELECT status, COUNT(*) FROM crates GROUP BY status ORDER BY COUNT(*) DESC, status",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let total = counts.iter().map(|(_, count)| count).sum::<u64>().max(1) as f64;
+
+    let mut start = 0.0;
+    Ok(counts
+        .into_iter()
+        .zip(PALETTE.iter().cycle())
+        .map(|((status, count), &color)| {
+            let end = start + count as f64 * 100.0 / total;
+            let slice = Slice {
+                status,
+                count,
+                color,
+                start,
+                end,
+            };
+            start = end;
+            slice
+        })
+        .collect())
+}
+
+/// Crates per repository host, most common first.
+fn forges(conn: &Connection) -> rusqlite::Result<Vec<Count>> {
+    let mut hosts: HashMap<String, u64> = HashMap::new();
+    let mut stmt = conn.prepare("SELECT repository FROM crates WHERE repository IS NOT NULL")?;
+    for url in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        let key = clone::normalize_url(&url?);
+        let host = key.split('/').next().unwrap_or_default().to_owned();
+        *hosts.entry(host).or_default() += 1;
+    }
+    Ok(top(hosts
+        .into_iter()
+        .map(|(label, count)| Count { label, count })))
+}
+
+/// The biggest checkouts on disk, with how many crates each one holds.
+fn largest_repos(conn: &Connection, output: &Path) -> rusqlite::Result<Vec<Repo>> {
+    let mut repos: Vec<Repo> = SharedRepos::load(conn, output)?
+        .into_checkouts()
+        .map(|(dir, crates)| {
+            let bytes = scan::dir_size(&dir);
+            Repo {
+                dir: dir.display().to_string(),
+                crates,
+                bytes,
+                mib: format!("{:.1}", bytes as f64 / (1024.0 * 1024.0)),
+            }
+        })
+        .collect();
+    repos.sort_by_key(|r| std::cmp::Reverse(r.bytes));
+    repos.truncate(TOP);
+    Ok(repos)
+}
+
+/// Failed crates grouped by status and whatever error code came with the failure.
+fn failures(conn: &Connection) -> rusqlite::Result<Vec<Count>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT status, http_status, git_error_code, COUNT(*) FROM crates
+         WHERE status IN {}
+         GROUP BY status, http_status, git_error_code",
+        CrateStatus::sql_list(&CrateStatus::FAILURES)
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        let status: String = row.get(0)?;
+        let http_status: Option<u16> = row.get(1)?;
+        let git_code: Option<i32> = row.get(2)?;
+        let label = match (http_status, git_code) {
+            (Some(http), _) => format!("{status} (HTTP {http})"),
+            (None, Some(code)) => format!("{status} (libgit2 error {code})"),
+            (None, None) => status,
+        };
+        Ok(Count {
+            label,
+            count: row.get(3)?,
+        })
+    })?;
+    Ok(top(rows.collect::<Result<Vec<_>, _>>()?))
+}
+
+fn crates(conn: &Connection) -> rusqlite::Result<Vec<Row>> {
+    conn.prepare(
+        "SELECT name, status, repository, max_version, downloads FROM crates ORDER BY name",
+    )?
+    .query_map([], |row| {
+        Ok(Row {
+            name: row.get(0)?,
+            status: row.get(1)?,
+            repository: row.get(2)?,
+            max_version: row.get(3)?,
+            downloads: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+/// The `TOP` largest counts, ties broken by label.
+fn top(counts: impl IntoIterator<Item = Count>) -> Vec<Count> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.label.cmp(&b.label)));
+    counts.truncate(TOP);
+    counts
+}
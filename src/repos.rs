@@ -0,0 +1,231 @@
+//! Checkouts that several crates live in. Every crate whose repository URL normalizes
+//! to the same key is looked for in the first one's checkout instead of being cloned
+//! again, so monorepos cost one clone however many crates they publish.
+
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::clone;
+use crate::status::CrateStatus;
+
+/// Statuses that, before `repo_dir` was recorded, meant a checkout in `output/<name>`.
+const OWN_CHECKOUT: [CrateStatus; 4] = [
+    CrateStatus::Cloned,
+    CrateStatus::Resumed,
+    CrateStatus::RepoMoved,
+    CrateStatus::NameMismatch,
+];
+
+/// A checkout that one or more crates' repository URLs resolve to.
+struct SharedRepo {
+    dir: PathBuf,
+    crates: u64,
+}
+
+/// Where a crate's repository stands, from [`SharedRepos::claim`].
+pub enum Lookup {
+    /// Not cloned yet.
+    New,
+    /// Already checked out here.
+    Shared(PathBuf),
+    /// Checked out, but holding `--max-crates-per-repo` crates already.
+    OverCap,
+}
+
+/// Every checkout recorded so far, keyed by normalized repository URL, with how many
+/// crates each one holds.
+#[derive(Default)]
+pub struct SharedRepos(HashMap<String, SharedRepo>);
+
+impl SharedRepos {
+    /// The checkouts recorded in the database. Rows from before `repo_dir` existed are
+    /// in `output/<name>`.
+    pub fn load(conn: &Connection, output: &Path) -> rusqlite::Result<Self> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT name, repository, repo_dir FROM crates
+             WHERE repository IS NOT NULL AND (repo_dir IS NOT NULL OR status IN {})",
+            CrateStatus::sql_list(&OWN_CHECKOUT)
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        let mut repos = SharedRepos::default();
+        for row in rows {
+            let (name, repository, repo_dir) = row?;
+            let dir = repo_dir.map_or_else(|| output.join(&name), PathBuf::from);
+            if !dir.exists() {
+                continue;
+            }
+            repos
+                .0
+                .entry(clone::normalize_url(&repository))
+                .or_insert(SharedRepo { dir, crates: 0 })
+                .crates += 1;
+        }
+        Ok(repos)
+    }
+
+    /// Count one more crate from `repo` against its checkout, if there is one. A single
+    /// map lookup, with no network involved.
+    pub fn claim(&mut self, repo: &str, max_crates: Option<u64>) -> Lookup {
+        let Some(shared) = self.0.get_mut(&clone::normalize_url(repo)) else {
+            return Lookup::New;
+        };
+        shared.crates += 1;
+        if max_crates.is_some_and(|max| shared.crates > max) {
+            Lookup::OverCap
+        } else {
+            Lookup::Shared(shared.dir.clone())
+        }
+    }
+
+    /// Each checkout with the number of crates it holds.
+    pub fn into_checkouts(self) -> impl Iterator<Item = (PathBuf, u64)> {
+        self.0.into_values().map(|repo| (repo.dir, repo.crates))
+    }
+
+    /// Record `dir` as the checkout of `repo`, holding one crate.
+    pub fn insert(&mut self, repo: &str, dir: PathBuf) {
+        self.0
+            .insert(clone::normalize_url(repo), SharedRepo { dir, crates: 1 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use crate::{manifest, schema};
+    use std::fs;
+
+    const URL: &str = "https://github.com/example/monorepo";
+    const MEMBERS: usize = 50;
+
+    fn member(i: usize) -> String {
+        format!("mono-{i}")
+    }
+
+    /// A committed workspace with [`MEMBERS`] crates, the way a monorepo's checkout
+    /// looks after its first crate was cloned.
+    fn monorepo(dir: &Path) {
+        let names: Vec<String> = (0..MEMBERS).map(member).collect();
+        fs::write(
+            dir.join("Cargo.toml"),
+            format!("[workspace]\nmembers = {:?}\n", names),
+        )
+        .unwrap();
+        for name in &names {
+            fs::create_dir_all(dir.join(name).join("src")).unwrap();
+            fs::write(
+                dir.join(name).join("Cargo.toml"),
+                format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n"),
+            )
+            .unwrap();
+            fs::write(dir.join(name).join("src/lib.rs"), "").unwrap();
+        }
+
+        let repo = git2::Repository::init(dir).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("bugbot", "bugbot@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "fixture", &tree, &[])
+            .unwrap();
+    }
+
+    /// A database where the first member was cloned into `checkout`.
+    fn database(checkout: &Path) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        schema::migrate(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO crates (name, repository, status, repo_dir) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![
+                member(0),
+                URL,
+                CrateStatus::Cloned,
+                checkout.to_string_lossy()
+            ],
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn finds_every_member_in_the_first_checkout() {
+        let output = TempDir::new("repos");
+        let checkout = output.path().join(member(0));
+        fs::create_dir(&checkout).unwrap();
+        monorepo(&checkout);
+        let conn = database(&checkout);
+
+        let mut repos = SharedRepos::load(&conn, output.path()).unwrap();
+        // Spellings crates.io really has for one repository.
+        let spellings = [
+            URL.to_owned(),
+            format!("{URL}.git"),
+            format!("{URL}/"),
+            URL.replace("example/monorepo", "Example/MonoRepo"),
+            format!("{URL}/tree/main/{}", member(7)),
+        ];
+        for i in 1..MEMBERS {
+            let url = &spellings[i % spellings.len()];
+            let Lookup::Shared(dir) = repos.claim(url, None) else {
+                panic!("{} from {} wasn't found in the checkout", member(i), url);
+            };
+            assert_eq!(dir, checkout);
+            let manifest = manifest::find_package(&dir, &member(i)).unwrap();
+            assert_eq!(manifest, checkout.join(member(i)).join("Cargo.toml"));
+        }
+        assert_eq!(
+            repos.into_checkouts().collect::<Vec<_>>(),
+            [(checkout, MEMBERS as u64)]
+        );
+    }
+
+    #[test]
+    fn caps_crates_per_checkout() {
+        let output = TempDir::new("repos");
+        let checkout = output.path().join(member(0));
+        fs::create_dir(&checkout).unwrap();
+        monorepo(&checkout);
+        let conn = database(&checkout);
+
+        let mut repos = SharedRepos::load(&conn, output.path()).unwrap();
+        assert!(matches!(repos.claim(URL, Some(3)), Lookup::Shared(_)));
+        assert!(matches!(repos.claim(URL, Some(3)), Lookup::Shared(_)));
+        for _ in 3..MEMBERS {
+            assert!(matches!(repos.claim(URL, Some(3)), Lookup::OverCap));
+        }
+    }
+
+    #[test]
+    fn new_repos_are_shared_once_inserted() {
+        let output = TempDir::new("repos");
+        let conn = Connection::open_in_memory().unwrap();
+        schema::migrate(&conn).unwrap();
+        let mut repos = SharedRepos::load(&conn, output.path()).unwrap();
+
+        assert!(matches!(repos.claim(URL, None), Lookup::New));
+        repos.insert(URL, output.path().join(member(0)));
+        assert!(matches!(
+            repos.claim(&format!("{URL}.git"), None),
+            Lookup::Shared(dir) if dir == output.path().join(member(0))
+        ));
+    }
+
+    #[test]
+    fn skips_checkouts_missing_from_disk() {
+        let output = TempDir::new("repos");
+        let conn = database(&output.path().join(member(0)));
+        let mut repos = SharedRepos::load(&conn, output.path()).unwrap();
+        assert!(matches!(repos.claim(URL, None), Lookup::New));
+    }
+}
@@ -0,0 +1,118 @@
+//! The `--clone-rules` file: clone options for particular crates, picked by name.
+//!
+//! ```toml
+//! [[rule]]
+//! crate = "tokio-*"
+//! protocol = "ssh"
+//!
+//! [[rule]]
+//! crate = "*-sys"
+//! normalize_line_endings = true
+//! env = { GIT_HTTP_LOW_SPEED_TIME = "600" }
+//! ```
+//!
+//! Rules are tried in order and the first whose pattern matches wins; options it
+//! doesn't set keep the values from the command line.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::clone::{CloneOptions, Protocol};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    /// Crate name pattern; `*` matches any run of characters and `?` any one.
+    #[serde(rename = "crate")]
+    pattern: String,
+    protocol: Option<Protocol>,
+    normalize_line_endings: Option<bool>,
+    shared_objects: Option<PathBuf>,
+    ssh_key: Option<PathBuf>,
+    max_auth_attempts: Option<u32>,
+    /// Added to `--clone-env`, replacing variables of the same name.
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+pub struct Rules(Vec<Rule>);
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        let file: RulesFile = toml::from_str(&text)
+            .with_context(|| format!("invalid clone rules file {}", path.display()))?;
+        Ok(Rules(file.rule))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// `base` with the first rule matching `name` applied, or `None` if none does.
+    pub fn options_for(&self, name: &str, base: &CloneOptions) -> Option<CloneOptions> {
+        let rule = self.0.iter().find(|rule| glob_match(&rule.pattern, name))?;
+        let mut opts = base.clone();
+        if let Some(protocol) = rule.protocol {
+            opts.protocol = Some(protocol);
+        }
+        if let Some(normalize) = rule.normalize_line_endings {
+            opts.exact_bytes = !normalize;
+        }
+        if let Some(dir) = &rule.shared_objects {
+            opts.shared_objects = Some(dir.clone());
+        }
+        if let Some(key) = &rule.ssh_key {
+            opts.auth.ssh_key = Some(key.clone());
+        }
+        if let Some(max) = rule.max_auth_attempts {
+            opts.auth.max_attempts = max;
+        }
+        for (key, value) in &rule.env {
+            opts.git_env.retain(|(k, _)| k != key);
+            opts.git_env.push((key.clone(), value.clone()));
+        }
+        Some(opts)
+    }
+}
+
+/// Whether `name` matches `pattern` in full, with `*` and `?` as wildcards.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of `name` it has swallowed so far.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    n = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
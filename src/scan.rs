@@ -0,0 +1,149 @@
+//! Cheap content scans over a crate's Rust sources.
+
+use proc_macro2::{TokenStream, TokenTree};
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::visit::{self, Visit};
+
+/// Every `.rs` file belonging to the package rooted at `package_dir`.
+///
+/// Hidden directories and `target/` are skipped, and so are subdirectories with their
+/// own `Cargo.toml`: those are other packages (workspace members, examples) that would
+/// otherwise be counted against this crate.
+pub fn rust_files(package_dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut stack = vec![package_dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_type.is_dir() {
+                if !file_name.starts_with('.')
+                    && file_name != "target"
+                    && !path.join("Cargo.toml").exists()
+                {
+                    stack.push(path);
+                }
+            } else if file_type.is_file() && file_name.ends_with(".rs") {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
+
+/// Total size of the files under `dir`, not following symlinks.
+pub fn dir_size(dir: &Path) -> u64 {
+    let mut total = 0;
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                stack.push(entry.path());
+            } else {
+                total += metadata.len();
+            }
+        }
+    }
+    total
+}
+
+/// Whether the package at `package_dir` has tests: a `tests/` directory with Rust files
+/// in it, or a `#[test]` (or `#[tokio::test]` and the like) function anywhere.
+///
+/// Attributes are only recognized at the start of a line, which keeps commented-out
+/// tests from counting and is how rustfmt leaves them.
+pub fn has_tests(package_dir: &Path) -> bool {
+    let files = rust_files(package_dir);
+    let tests_dir = package_dir.join("tests");
+    files.iter().any(|path| path.starts_with(&tests_dir))
+        || files.iter().any(|path| {
+            fs::read_to_string(path).is_ok_and(|source| source.lines().any(is_test_attribute))
+        })
+}
+
+fn is_test_attribute(line: &str) -> bool {
+    let Some(attr) = line.trim_start().strip_prefix("#[") else {
+        return false;
+    };
+    let path = attr.split([']', '(']).next().unwrap_or_default().trim();
+    path == "test" || path.ends_with("::test")
+}
+
+/// Count `unsafe` blocks, functions, impls and traits in the package at `package_dir`.
+///
+/// Files are parsed with syn, so `unsafe` inside comments and string literals is never
+/// counted. Macro bodies are opaque to syn, so their tokens are counted directly, and
+/// files syn can't parse fall back to the same token count.
+pub fn count_unsafe(package_dir: &Path) -> u64 {
+    rust_files(package_dir)
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|source| match syn::parse_file(&source) {
+            Ok(file) => {
+                let mut counter = UnsafeCounter(0);
+                counter.visit_file(&file);
+                counter.0
+            }
+            Err(_) => source
+                .parse::<TokenStream>()
+                .map(count_unsafe_tokens)
+                .unwrap_or(0),
+        })
+        .sum()
+}
+
+struct UnsafeCounter(u64);
+
+impl<'ast> Visit<'ast> for UnsafeCounter {
+    fn visit_expr_unsafe(&mut self, node: &'ast syn::ExprUnsafe) {
+        self.0 += 1;
+        visit::visit_expr_unsafe(self, node);
+    }
+
+    fn visit_signature(&mut self, node: &'ast syn::Signature) {
+        self.0 += u64::from(node.unsafety.is_some());
+        visit::visit_signature(self, node);
+    }
+
+    fn visit_item_impl(&mut self, node: &'ast syn::ItemImpl) {
+        self.0 += u64::from(node.unsafety.is_some());
+        visit::visit_item_impl(self, node);
+    }
+
+    fn visit_item_trait(&mut self, node: &'ast syn::ItemTrait) {
+        self.0 += u64::from(node.unsafety.is_some());
+        visit::visit_item_trait(self, node);
+    }
+
+    fn visit_macro(&mut self, node: &'ast syn::Macro) {
+        self.0 += count_unsafe_tokens(node.tokens.clone());
+        visit::visit_macro(self, node);
+    }
+}
+
+/// Count `unsafe` keywords in a token stream. Tokenizing already drops comments and
+/// turns strings into single literal tokens, so neither can produce a false hit.
+fn count_unsafe_tokens(tokens: TokenStream) -> u64 {
+    tokens
+        .into_iter()
+        .map(|tree| match tree {
+            TokenTree::Ident(ident) => u64::from(ident == "unsafe"),
+            TokenTree::Group(group) => count_unsafe_tokens(group.stream()),
+            _ => 0,
+        })
+        .sum()
+}
@@ -0,0 +1,442 @@
+//! Creating the database and upgrading older ones to the current schema.
+//!
+//! The version lives under `schema_version` in the `meta` table. A new database starts
+//! at the original three-column `crates` table (v1) and goes through every migration,
+//! exactly like an old one being upgraded, so the two can't drift apart. Databases from
+//! before versioning count as v1; adding a column they already have is skipped.
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::{debug, info};
+
+use crate::texts;
+
+const SCHEMA_VERSION: &str = "schema_version";
+
+/// One change to the schema.
+enum Step {
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        decl: &'static str,
+    },
+    Sql(&'static str),
+    Run(fn(&Connection) -> rusqlite::Result<()>),
+}
+
+const fn add(table: &'static str, column: &'static str, decl: &'static str) -> Step {
+    Step::AddColumn {
+        table,
+        column,
+        decl,
+    }
+}
+
+/// `MIGRATIONS[i]` takes a database from version `i + 1` to `i + 2`. Only ever append.
+const MIGRATIONS: &[&[Step]] = &[
+    // v2: post-clone hooks
+    &[add("crates", "hook_status", "INTEGER")],
+    // v3: owners
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS owners (
+            crate_name  TEXT NOT NULL,
+            login       TEXT NOT NULL,
+            kind        TEXT,
+            name        TEXT,
+            PRIMARY KEY (crate_name, login)
+        )",
+    )],
+    // v4: advisory scan
+    &[
+        add("crates", "advisory_count", "INTEGER"),
+        Step::Sql(
+            "CREATE TABLE IF NOT EXISTS vulnerabilities (
+                crate_name   TEXT NOT NULL,
+                advisory_id  TEXT NOT NULL,
+                package      TEXT NOT NULL,
+                version      TEXT NOT NULL,
+                PRIMARY KEY (crate_name, advisory_id, package, version)
+            )",
+        ),
+    ],
+    // v5: unsafe scan
+    &[add("crates", "unsafe_count", "INTEGER")],
+    // v6: reverse dependency counts
+    &[
+        add("crates", "reverse_dep_count", "INTEGER"),
+        add("crates", "reverse_dep_fetched_at", "INTEGER"),
+    ],
+    // v7: protocol rewriting
+    &[add("crates", "clone_protocol", "TEXT")],
+    // v8: version tag checkout
+    &[
+        add("crates", "checked_out_version", "TEXT"),
+        add("crates", "checked_out_yanked", "INTEGER"),
+    ],
+    // v9: crate metadata, with long texts kept in their own table
+    &[
+        add("crates", "downloads", "INTEGER"),
+        add("crates", "max_version", "TEXT"),
+        add("crates", "updated_at", "INTEGER"),
+        Step::Sql(
+            "CREATE TABLE IF NOT EXISTS categories (
+                crate_name  TEXT NOT NULL,
+                slug        TEXT NOT NULL,
+                PRIMARY KEY (crate_name, slug)
+            )",
+        ),
+        Step::Run(texts::create_table),
+        Step::Run(texts::migrate),
+    ],
+    // v10: shared checkouts
+    &[add("crates", "repo_dir", "TEXT")],
+    // v11: redirect resolution
+    &[add("crates", "resolved_url", "TEXT")],
+    // v12: numeric error codes
+    &[
+        add("crates", "http_status", "INTEGER"),
+        add("crates", "git_error_code", "INTEGER"),
+        add("crates", "git_error_class", "INTEGER"),
+    ],
+    // v13: vendored sources left out of the checkout
+    &[add("crates", "vendored_excluded", "INTEGER")],
+    // v14: published versions matched to tagged commits
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS versions (
+            crate_name  TEXT NOT NULL,
+            version     TEXT NOT NULL,
+            tag         TEXT NOT NULL,
+            commit_id   TEXT NOT NULL,
+            yanked      INTEGER NOT NULL,
+            PRIMARY KEY (crate_name, version)
+        )",
+    )],
+    // v15: Git LFS
+    &[add("crates", "lfs", "TEXT")],
+    // v16: published tarballs
+    &[add("crates", "tarball_version", "TEXT")],
+    // v17: edition and MSRV from the crate's manifest
+    &[
+        add("crates", "edition", "TEXT"),
+        add("crates", "rust_version", "TEXT"),
+    ],
+    // v18: repository URLs from --repo-url-overrides
+    &[add("crates", "overridden", "INTEGER")],
+    // v19: SPDX license
+    &[add("crates", "license", "TEXT")],
+    // v20: metadata staleness
+    &[add("crates", "metadata_fetched_at", "INTEGER")],
+    // v21: published files that don't match the repository
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS discrepancies (
+            crate_name  TEXT NOT NULL,
+            version     TEXT NOT NULL,
+            path        TEXT NOT NULL,
+            kind        TEXT NOT NULL,
+            PRIMARY KEY (crate_name, version, path)
+        )",
+    )],
+    // v22: runs stopped by crates.io blocking us
+    &[Step::Sql(
+        "CREATE TABLE IF NOT EXISTS api_bans (
+            at          INTEGER NOT NULL,
+            crate_name  TEXT NOT NULL,
+            message     TEXT NOT NULL
+        )",
+    )],
+    // v23: --scan-tests
+    &[add("crates", "has_tests", "INTEGER")],
+    // v24: checkouts uploaded by --output-url
+    &[add("crates", "archive_url", "TEXT")],
+];
+
+/// The version this binary's code expects.
+pub const CURRENT_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// Bring the database up to [`CURRENT_VERSION`], creating it from scratch if it's empty.
+/// Each migration commits on its own, so an interrupted upgrade resumes where it stopped.
+pub fn migrate(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS meta (
+            key    TEXT PRIMARY KEY,
+            value  TEXT NOT NULL
+        )",
+        [],
+    )
+    .context("failed to create meta table")?;
+
+    let fresh = !table_exists(conn, "crates")?;
+    let version = match stored_version(conn)? {
+        Some(version) => version,
+        None => {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS crates (
+                    name        TEXT PRIMARY KEY,
+                    repository  TEXT,
+                    status      TEXT NOT NULL
+                )",
+                [],
+            )
+            .context("failed to create crates table")?;
+            1
+        }
+    };
+    if version > CURRENT_VERSION {
+        bail!(
+            "database schema is v{}, newer than this binary's v{}; upgrade bugbot9000",
+            version,
+            CURRENT_VERSION
+        );
+    }
+    if version < CURRENT_VERSION && !fresh {
+        info!(
+            "Upgrading database schema from v{} to v{}",
+            version, CURRENT_VERSION
+        );
+    }
+
+    upgrade(conn, version, CURRENT_VERSION)
+}
+
+/// Apply the migrations that take a database at `from` up to `to`.
+fn upgrade(conn: &Connection, from: u32, to: u32) -> Result<()> {
+    let pending = MIGRATIONS
+        .iter()
+        .enumerate()
+        .take(to as usize - 1)
+        .skip(from as usize - 1);
+    for (i, steps) in pending {
+        let next = i as u32 + 2;
+        debug!("applying schema migration to v{}", next);
+        let tx = conn.unchecked_transaction()?;
+        for step in steps.iter() {
+            apply(&tx, step).with_context(|| format!("schema migration to v{} failed", next))?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![SCHEMA_VERSION, next.to_string()],
+        )?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn table_exists(conn: &Connection, table: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get(0),
+    )
+}
+
+fn stored_version(conn: &Connection) -> Result<Option<u32>> {
+    let version: Option<String> = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = ?1",
+            [SCHEMA_VERSION],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match version {
+        Some(v) => Ok(Some(
+            v.parse()
+                .with_context(|| format!("bad schema_version {:?}", v))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+fn apply(conn: &Connection, step: &Step) -> rusqlite::Result<()> {
+    match *step {
+        Step::AddColumn {
+            table,
+            column,
+            decl,
+        } => {
+            let exists: bool = conn.query_row(
+                &format!("SELECT COUNT(*) > 0 FROM pragma_table_info('{table}') WHERE name = ?1"),
+                [column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                conn.execute(
+                    &format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"),
+                    [],
+                )?;
+            }
+            Ok(())
+        }
+        Step::Sql(sql) => conn.execute_batch(sql),
+        Step::Run(run) => run(conn),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// The database every release before schema versioning wrote.
+    fn v1(conn: &Connection) {
+        conn.execute_batch(
+            "CREATE TABLE crates (
+                name        TEXT PRIMARY KEY,
+                repository  TEXT,
+                status      TEXT NOT NULL
+            );
+            INSERT INTO crates VALUES ('serde', 'https://github.com/serde-rs/serde', 'cloned');
+            INSERT INTO crates VALUES ('empty', NULL, 'no_repo');
+            INSERT INTO crates VALUES ('broken', 'https://example.com/broken', 'failed');",
+        )
+        .unwrap();
+    }
+
+    /// What [`migrate`] does before the first migration: a `meta` table with the
+    /// version at 1.
+    fn migrate_meta(conn: &Connection) {
+        conn.execute_batch(
+            "CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             INSERT INTO meta VALUES ('schema_version', '1');",
+        )
+        .unwrap();
+    }
+
+    fn rows(conn: &Connection) -> Vec<(String, Option<String>, String)> {
+        conn.prepare("SELECT name, repository, status FROM crates ORDER BY name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        conn.prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    /// Everything `steps` should have left behind.
+    fn assert_applied(conn: &Connection, steps: &[Step], version: u32) {
+        for step in steps {
+            match *step {
+                Step::AddColumn { table, column, .. } => assert!(
+                    columns(conn, table).iter().any(|c| c == column),
+                    "v{version} should add {table}.{column}"
+                ),
+                Step::Sql(sql) => {
+                    let table = sql
+                        .split_whitespace()
+                        .skip_while(|word| *word != "EXISTS")
+                        .nth(1)
+                        .unwrap();
+                    assert!(
+                        table_exists(conn, table).unwrap(),
+                        "v{version} should create {table}"
+                    );
+                }
+                Step::Run(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn upgrades_v1_one_migration_at_a_time() {
+        let conn = Connection::open_in_memory().unwrap();
+        v1(&conn);
+        let before = rows(&conn);
+        migrate_meta(&conn);
+
+        for (i, steps) in MIGRATIONS.iter().enumerate() {
+            let to = i as u32 + 2;
+            upgrade(&conn, to - 1, to).unwrap();
+            assert_eq!(stored_version(&conn).unwrap(), Some(to));
+            assert_applied(&conn, steps, to);
+            assert_eq!(rows(&conn), before, "v{to} lost or changed rows");
+        }
+        assert_eq!(stored_version(&conn).unwrap(), Some(CURRENT_VERSION));
+    }
+
+    #[test]
+    fn upgrades_a_v1_file_in_one_go() {
+        let dir = TempDir::new("schema");
+        let path = dir.path().join("bugbot.sqlite");
+        {
+            let conn = Connection::open(&path).unwrap();
+            v1(&conn);
+        }
+        let before = rows(&Connection::open(&path).unwrap());
+
+        let conn = Connection::open(&path).unwrap();
+        migrate(&conn).unwrap();
+        drop(conn);
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(stored_version(&conn).unwrap(), Some(CURRENT_VERSION));
+        assert_eq!(rows(&conn), before);
+        for (i, steps) in MIGRATIONS.iter().enumerate() {
+            assert_applied(&conn, steps, i as u32 + 2);
+        }
+        // Already current: nothing left to do.
+        migrate(&conn).unwrap();
+        assert_eq!(rows(&conn), before);
+    }
+
+    #[test]
+    fn moves_text_columns_of_unversioned_databases() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE crates (
+                name         TEXT PRIMARY KEY,
+                repository   TEXT,
+                status       TEXT NOT NULL,
+                hook_status  INTEGER,
+                description  TEXT,
+                last_error   TEXT
+            );
+            INSERT INTO crates VALUES ('a', 'https://example.com/a', 'failed', 0, 'An a', 'timed out');
+            INSERT INTO crates VALUES ('b', 'https://example.com/b', 'cloned', NULL, NULL, NULL);",
+        )
+        .unwrap();
+        migrate(&conn).unwrap();
+
+        assert!(!columns(&conn, "crates").iter().any(|c| c == "description"));
+        assert_eq!(
+            texts::load(&conn, "a", texts::DESCRIPTION)
+                .unwrap()
+                .as_deref(),
+            Some("An a")
+        );
+        assert_eq!(
+            texts::load(&conn, "a", texts::LAST_ERROR)
+                .unwrap()
+                .as_deref(),
+            Some("timed out")
+        );
+        assert_eq!(texts::load(&conn, "b", texts::DESCRIPTION).unwrap(), None);
+        let hook_status: Option<i64> = conn
+            .query_row(
+                "SELECT hook_status FROM crates WHERE name = 'a'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(hook_status, Some(0));
+    }
+
+    #[test]
+    fn refuses_newer_databases() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        conn.execute(
+            "UPDATE meta SET value = ?1 WHERE key = ?2",
+            params![(CURRENT_VERSION + 1).to_string(), SCHEMA_VERSION],
+        )
+        .unwrap();
+        assert!(migrate(&conn).is_err());
+    }
+}
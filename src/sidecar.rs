@@ -0,0 +1,44 @@
+//! `bugbot.json`, the description of a checkout written into it by
+//! `--output-manifest-per-crate`, so it still says what it is once copied away from
+//! the database.
+
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const FILE_NAME: &str = "bugbot.json";
+
+#[derive(Serialize)]
+pub struct Sidecar<'a> {
+    #[serde(rename = "crate")]
+    pub name: &'a str,
+    pub version: &'a str,
+    pub repository: &'a str,
+    pub head: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub cloned_at: u64,
+}
+
+/// Write `sidecar` into the checkout at `dir` and keep it out of `git status`.
+pub fn write(dir: &Path, sidecar: &Sidecar) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(sidecar).map_err(io::Error::other)?;
+    fs::write(dir.join(FILE_NAME), json + "\n")?;
+
+    let exclude = dir.join(".git/info/exclude");
+    let existing = fs::read_to_string(&exclude).unwrap_or_default();
+    if !existing.lines().any(|line| line == format!("/{FILE_NAME}")) {
+        if let Some(parent) = exclude.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&exclude)?;
+        if !existing.is_empty() && !existing.ends_with('\n') {
+            writeln!(file)?;
+        }
+        writeln!(file, "/{FILE_NAME}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Pushing per-crate counters and clone timings to a StatsD server, for `--statsd`.
+//!
+//! Metrics go out over UDP as each crate finishes. Nothing waits for the server: a
+//! packet that can't be sent is dropped, so a missing StatsD never slows the run.
+
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use tracing::debug;
+
+pub struct StatsD {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsD {
+    /// Send to `addr` (`host:port`), naming every metric `<prefix>.<metric>`.
+    pub fn connect(addr: &str, prefix: &str) -> io::Result<Self> {
+        let target = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} didn't resolve", addr))
+        })?;
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(StatsD {
+            socket,
+            prefix: prefix.to_owned(),
+        })
+    }
+
+    /// Add `n` to the counter `metric`.
+    pub fn count(&self, metric: &str, n: u64) {
+        self.send(&format!("{}.{}:{}|c", self.prefix, metric, n));
+    }
+
+    /// Record one `took` sample of the timer `metric`.
+    pub fn timing(&self, metric: &str, took: Duration) {
+        self.send(&format!(
+            "{}.{}:{}|ms",
+            self.prefix,
+            metric,
+            took.as_millis()
+        ));
+    }
+
+    fn send(&self, line: &str) {
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            debug!("dropped StatsD metric {}: {}", line, e);
+        }
+    }
+}
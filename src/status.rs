@@ -0,0 +1,139 @@
+//! Every status a crate can be recorded with. The table below is the only place one is
+//! spelled out: the enum, `--list-statuses`, the strings stored in the `status` column
+//! and the groups of statuses the rest of the code asks about all come from it.
+
+use rusqlite::types::{ToSql, ToSqlOutput};
+use std::fmt;
+
+macro_rules! statuses {
+    ($($variant:ident = $name:literal: $description:expr,)*) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum CrateStatus {
+            $($variant,)*
+        }
+
+        impl CrateStatus {
+            /// Every status, in the order `--list-statuses` prints them.
+            pub const ALL: &[CrateStatus] = &[$(CrateStatus::$variant,)*];
+
+            /// The value stored in the `status` column.
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(CrateStatus::$variant => $name,)*
+                }
+            }
+
+            /// What the status means, for `--list-statuses`.
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(CrateStatus::$variant => $description,)*
+                }
+            }
+        }
+    };
+}
+
+statuses! {
+    Pending = "pending": "seen, but not cloned yet (or the clone was interrupted)",
+    MetadataOk = "metadata_ok": "metadata fetched by --state-only, not cloned yet",
+    Cloned = "cloned": "cloned into repo_dir",
+    Resumed = "resumed": "cloned by finishing an interrupted earlier clone",
+    RepoMoved = "repo_moved": "cloned, then fetched again from the new URL its repository moved to",
+    SharedRepo = "shared_repo": "found in a checkout already cloned for another crate",
+    TarballOk = "tarball_ok": "published .crate downloaded by --tarball-only, not cloned",
+    NoRepo = "no_repo": "crates.io lists no repository URL",
+    EmptyRepo = "empty_repo": "the repository has no commits",
+    NameMismatch = "name_mismatch": "no Cargo.toml in the checkout names this crate",
+    RepoCap = "repo_cap": "skipped by --max-crates-per-repo",
+    TooFewDependents = "too_few_dependents": "skipped by --min-dependents",
+    EditionFiltered = "edition_filtered": "cloned, but below --min-edition or --min-rust-version",
+    TestsFiltered = "tests_filtered": "cloned, but has no tests (--require-tests)",
+    LicenseFiltered = "license_filtered": "skipped by --license-filter",
+    AuthRequired = "auth_required": "the repository asked for credentials that didn't work",
+    HostUnreachable = "host_unreachable": "not cloned: the repository's host didn't answer",
+    TlsError = "tls_error": "the host's TLS certificate wasn't trusted; see --ca-bundle",
+    Failed = "failed": "the clone failed; see last_error",
+    Panicked = "panicked": "processing it panicked (--keep-going-on-panic); see last_error",
+    HeadTagMismatch = "head_tag_mismatch":
+        "HEAD isn't the version tag's commit after checkout (--verify-head-matches-tag)",
+    MetadataError = "metadata_error": "fetching metadata from crates.io failed",
+}
+
+impl CrateStatus {
+    /// Statuses of crates whose checkout is in the mirror.
+    pub const CHECKED_OUT: [CrateStatus; 4] = [
+        CrateStatus::Cloned,
+        CrateStatus::Resumed,
+        CrateStatus::RepoMoved,
+        CrateStatus::SharedRepo,
+    ];
+
+    /// Statuses a crate ends up in when something went wrong.
+    pub const FAILURES: [CrateStatus; 7] = [
+        CrateStatus::Failed,
+        CrateStatus::Panicked,
+        CrateStatus::HeadTagMismatch,
+        CrateStatus::AuthRequired,
+        CrateStatus::TlsError,
+        CrateStatus::HostUnreachable,
+        CrateStatus::MetadataError,
+    ];
+
+    /// The status stored as `s`.
+    pub fn parse(s: &str) -> Option<Self> {
+        CrateStatus::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_str() == s)
+    }
+
+    /// `statuses` as an SQL list such as `('cloned', 'resumed')`, for `status IN`.
+    pub fn sql_list(statuses: &[CrateStatus]) -> String {
+        let quoted: Vec<String> = statuses
+            .iter()
+            .map(|status| format!("'{}'", status.as_str()))
+            .collect();
+        format!("({})", quoted.join(", "))
+    }
+}
+
+impl fmt::Display for CrateStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<CrateStatus> for String {
+    fn from(status: CrateStatus) -> Self {
+        status.as_str().to_owned()
+    }
+}
+
+impl ToSql for CrateStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn every_status_parses_back() {
+        for &status in CrateStatus::ALL {
+            assert_eq!(CrateStatus::parse(status.as_str()), Some(status));
+        }
+        let names: HashSet<&str> = CrateStatus::ALL.iter().map(|s| s.as_str()).collect();
+        assert_eq!(names.len(), CrateStatus::ALL.len());
+    }
+
+    #[test]
+    fn sql_lists_are_quoted() {
+        assert_eq!(
+            CrateStatus::sql_list(&[CrateStatus::Cloned, CrateStatus::RepoCap]),
+            "('cloned', 'repo_cap')"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Matching published crate versions to git tags and checking them out.
+
+use crates_io_api::Version;
+use git2::{build::CheckoutBuilder, Oid, Repository};
+
+/// Versions newest first by semver, skipping yanked ones unless `include_yanked`.
+/// Versions that don't parse as semver are ignored.
+pub fn versions_newest_first(versions: &[Version], include_yanked: bool) -> Vec<&Version> {
+    let mut parsed: Vec<_> = versions
+        .iter()
+        .filter(|v| include_yanked || !v.yanked)
+        .filter_map(|v| {
+            semver::Version::parse(&v.num)
+                .ok()
+                .map(|parsed| (parsed, v))
+        })
+        .collect();
+    parsed.sort_by(|(a, _), (b, _)| b.cmp(a));
+    parsed.into_iter().map(|(_, v)| v).collect()
+}
+
+/// Tag names projects commonly use for a release, most specific first so that
+/// `<crate>-v1.0.0` in a monorepo wins over another crate's bare `v1.0.0`.
+fn candidate_tags(crate_name: &str, version: &str) -> [String; 6] {
+    [
+        format!("{crate_name}-v{version}"),
+        format!("{crate_name}-{version}"),
+        format!("{crate_name}@{version}"),
+        format!("{crate_name}/v{version}"),
+        format!("v{version}"),
+        version.to_owned(),
+    ]
+}
+
+/// The tag for `version` of `crate_name` and the commit it points at, if any.
+pub fn find_version_tag(
+    repo: &Repository,
+    crate_name: &str,
+    version: &str,
+) -> Option<(String, Oid)> {
+    candidate_tags(crate_name, version)
+        .into_iter()
+        .find_map(|tag| {
+            let commit = repo
+                .revparse_single(&format!("refs/tags/{tag}"))
+                .and_then(|obj| obj.peel_to_commit())
+                .ok()?;
+            Some((tag, commit.id()))
+        })
+}
+
+/// Detach HEAD at `commit` and force the working tree to match it.
+pub fn checkout(repo: &Repository, commit: Oid) -> Result<(), git2::Error> {
+    let object = repo.find_object(commit, None)?;
+    repo.checkout_tree(&object, Some(CheckoutBuilder::new().force()))?;
+    repo.set_head_detached(commit)
+}
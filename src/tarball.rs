@@ -0,0 +1,174 @@
+//! Downloading published `.crate` files from the crates.io CDN, for `--fetch-tarball`,
+//! and comparing them with the repository for `--compare-tarball-to-git`.
+
+use anyhow::{bail, Context, Result};
+use crates_index::{Crate, Version};
+use flate2::read::GzDecoder;
+use git2::{Oid, Repository};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CDN: &str = "https://static.crates.io/crates";
+
+pub struct Fetcher {
+    client: reqwest::blocking::Client,
+}
+
+impl Fetcher {
+    pub fn new() -> Result<Self> {
+        let client = crate::tls::http_client()
+            .timeout(Duration::from_secs(300))
+            .build()
+            .context("could not create HTTP client")?;
+        Ok(Fetcher { client })
+    }
+
+    /// Download the newest unyanked version of `krate` into `dir` as
+    /// `<name>-<version>.crate`, checking it against the index's checksum first.
+    /// Returns the version and where it was saved.
+    pub fn fetch(&self, krate: &Crate, dir: &Path) -> Result<(String, PathBuf)> {
+        let Some(version) = newest_version(krate) else {
+            bail!("{} has no unyanked versions", krate.name());
+        };
+        let file_name = format!("{}-{}.crate", krate.name(), version.version());
+        let url = format!("{}/{}/{}", CDN, krate.name(), file_name);
+        let bytes = self
+            .client
+            .get(&url)
+            .send()
+            .and_then(|resp| resp.error_for_status())
+            .and_then(|resp| resp.bytes())
+            .with_context(|| format!("could not download {}", url))?;
+        if Sha256::digest(&bytes).as_slice() != version.checksum() {
+            bail!("{} doesn't match the checksum in the index", url);
+        }
+
+        // Written under a temporary name so a cut-off download never looks complete.
+        let path = dir.join(&file_name);
+        let partial = path.with_extension("crate.part");
+        fs::write(&partial, &bytes)
+            .and_then(|()| fs::rename(&partial, &path))
+            .with_context(|| format!("could not write {}", path.display()))?;
+        Ok((version.version().to_owned(), path))
+    }
+}
+
+/// The newest unyanked version by semver.
+fn newest_version(krate: &Crate) -> Option<&Version> {
+    krate
+        .versions()
+        .iter()
+        .filter(|v| !v.is_yanked())
+        .filter_map(|v| Some((semver::Version::parse(v.version()).ok()?, v)))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, v)| v)
+}
+
+/// A file in a published `.crate` that isn't what the repository has.
+pub struct Discrepancy {
+    /// Relative to the package root.
+    pub path: String,
+    /// `added` when git doesn't have the file at all, `modified` when it differs.
+    pub kind: &'static str,
+}
+
+/// Files cargo writes into every package itself, so they never match git.
+const GENERATED: &[&str] = &[".cargo_vcs_info.json", "Cargo.toml", "Cargo.lock"];
+
+/// Compare the `.crate` at `crate_file` with the commit in `repo` it was published
+/// from. That's the one `.cargo_vcs_info.json` names; packages without one (published
+/// from a dirty tree, or before cargo wrote it) use `fallback`, a tagged commit and
+/// the package's directory within the repository.
+pub fn compare(
+    crate_file: &Path,
+    repo: &Repository,
+    fallback: Option<(Oid, &Path)>,
+) -> Result<Vec<Discrepancy>> {
+    let files = read_crate(crate_file)?;
+    let vcs_info = files
+        .iter()
+        .find(|(path, _)| path == ".cargo_vcs_info.json")
+        .and_then(|(_, contents)| serde_json::from_slice::<VcsInfo>(contents).ok());
+    let (commit, package_dir) = match (vcs_info, fallback) {
+        (Some(info), _) => (
+            Oid::from_str(&info.git.sha1).context("bad sha1 in .cargo_vcs_info.json")?,
+            PathBuf::from(info.path_in_vcs),
+        ),
+        (None, Some((commit, dir))) => (commit, dir.to_owned()),
+        (None, None) => bail!(
+            "can't tell which commit {} was published from",
+            crate_file.display()
+        ),
+    };
+    let tree = repo
+        .find_commit(commit)
+        .and_then(|commit| commit.tree())
+        .with_context(|| format!("commit {} isn't in the repository", commit))?;
+
+    let mut found = Vec::new();
+    for (path, contents) in &files {
+        if GENERATED.contains(&path.as_str()) {
+            continue;
+        }
+        // The manifest as written is kept alongside the normalized one.
+        let in_git = package_dir.join(path.strip_suffix(".orig").unwrap_or(path));
+        let kind = match tree.get_path(&in_git) {
+            Err(_) => "added",
+            // Cargo follows symlinks, so there's nothing to compare a link's blob with.
+            Ok(entry) if entry.filemode() == 0o120000 => continue,
+            Ok(entry) => match entry
+                .to_object(repo)
+                .ok()
+                .and_then(|obj| obj.into_blob().ok())
+            {
+                Some(blob) if blob.content() == contents.as_slice() => continue,
+                Some(_) => "modified",
+                // A submodule, whose contents aren't in this repository.
+                None => continue,
+            },
+        };
+        found.push(Discrepancy {
+            path: path.clone(),
+            kind,
+        });
+    }
+    Ok(found)
+}
+
+#[derive(Deserialize)]
+struct VcsInfo {
+    git: VcsGit,
+    #[serde(default)]
+    path_in_vcs: String,
+}
+
+#[derive(Deserialize)]
+struct VcsGit {
+    sha1: String,
+}
+
+/// Every regular file in a `.crate`, with the leading `<name>-<version>/` removed.
+fn read_crate(crate_file: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let file = fs::File::open(crate_file)
+        .with_context(|| format!("could not open {}", crate_file.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut files = Vec::new();
+    let bad = || format!("could not read {}", crate_file.display());
+    for entry in archive.entries().with_context(bad)? {
+        let mut entry = entry.with_context(bad)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().with_context(bad)?;
+        let path = path.components().skip(1).collect::<PathBuf>();
+        let path = path.to_string_lossy().replace('\\', "/");
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).with_context(bad)?;
+        files.push((path, contents));
+    }
+    Ok(files)
+}
@@ -0,0 +1,117 @@
+//! Long free-text fields, kept out of the `crates` table so that scanning it stays cheap.
+//!
+//! Each value lives in `crate_texts` under its crate and field name, optionally
+//! zlib-compressed (`--compress-db`); the `compressed` column says which, so the flag
+//! can be turned on or off between runs.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::io::{Read, Write};
+
+pub const DESCRIPTION: &str = "description";
+pub const LAST_ERROR: &str = "last_error";
+/// The whole crates.io response, kept by `harvest` for `clone`.
+pub const METADATA: &str = "metadata";
+
+/// Below this many bytes compression wouldn't pay for its own header.
+const MIN_COMPRESSED_LEN: usize = 128;
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS crate_texts (
+            crate_name  TEXT NOT NULL,
+            field       TEXT NOT NULL,
+            value       BLOB NOT NULL,
+            compressed  INTEGER NOT NULL,
+            PRIMARY KEY (crate_name, field)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Move text columns that older databases kept in `crates` into `crate_texts`, then
+/// drop them. Meant to run inside a schema migration's transaction.
+pub fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('crates')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+
+    for field in [DESCRIPTION, LAST_ERROR] {
+        if !columns.iter().any(|c| c == field) {
+            continue;
+        }
+        conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO crate_texts (crate_name, field, value, compressed)
+                 SELECT name, ?1, CAST({field} AS BLOB), 0 FROM crates WHERE {field} IS NOT NULL"
+            ),
+            params![field],
+        )?;
+        conn.execute(&format!("ALTER TABLE crates DROP COLUMN {field}"), [])?;
+    }
+    Ok(())
+}
+
+/// Set `field` of `name` to `value`, or remove it for `None`.
+pub fn store(
+    conn: &Connection,
+    name: &str,
+    field: &str,
+    value: Option<&str>,
+    compress: bool,
+) -> rusqlite::Result<()> {
+    let Some(value) = value else {
+        conn.execute(
+            "DELETE FROM crate_texts WHERE crate_name = ?1 AND field = ?2",
+            params![name, field],
+        )?;
+        return Ok(());
+    };
+
+    let compressed = (compress && value.len() >= MIN_COMPRESSED_LEN)
+        .then(|| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(value.as_bytes())?;
+            encoder.finish()
+        })
+        .and_then(Result::ok);
+    let (bytes, is_compressed) = match &compressed {
+        Some(bytes) => (bytes.as_slice(), true),
+        None => (value.as_bytes(), false),
+    };
+    conn.execute(
+        "INSERT OR REPLACE INTO crate_texts (crate_name, field, value, compressed)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![name, field, bytes, is_compressed],
+    )?;
+    Ok(())
+}
+
+/// The value of `field` for `name`, decompressed, if there is one.
+pub fn load(conn: &Connection, name: &str, field: &str) -> rusqlite::Result<Option<String>> {
+    let row: Option<(Vec<u8>, bool)> = conn
+        .query_row(
+            "SELECT value, compressed FROM crate_texts WHERE crate_name = ?1 AND field = ?2",
+            params![name, field],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((bytes, compressed)) = row else {
+        return Ok(None);
+    };
+    let text = if compressed {
+        let mut text = String::new();
+        ZlibDecoder::new(bytes.as_slice())
+            .read_to_string(&mut text)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Blob, Box::new(e)))?;
+        text
+    } else {
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    Ok(Some(text))
+}
@@ -0,0 +1,135 @@
+//! Certificate settings for `--ca-bundle` and `--insecure-tls`, shared by every TLS
+//! connection the run makes: libgit2, the `git` CLI, and our HTTP clients. The
+//! crates.io API client builds its own HTTP client, so it only trusts the system's
+//! certificate store.
+//!
+//! libgit2 only trusts its own certificate store, so for it the bundle is checked in a
+//! `certificate_check` callback ([`check_git_certificate`]). These settings are
+//! process-wide: [`init`] is called once at startup and everything else reads them back.
+
+use anyhow::{Context, Result};
+use git2::{cert::Cert, CertificateCheckStatus};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyParam;
+use openssl::x509::{X509StoreContext, X509};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+#[derive(Default)]
+pub struct TlsOptions {
+    /// PEM file of extra CA certificates to trust.
+    pub ca_bundle: Option<PathBuf>,
+    /// Accept any certificate from a repository host.
+    pub insecure: bool,
+}
+
+struct Tls {
+    opts: TlsOptions,
+    certs: Vec<reqwest::Certificate>,
+    /// The same bundle, for checking libgit2's connections.
+    git_roots: Vec<X509>,
+}
+
+static TLS: OnceLock<Tls> = OnceLock::new();
+
+/// Load the CA bundle, if any. Must run before the first connection is made.
+pub fn init(opts: TlsOptions) -> Result<()> {
+    let mut certs = Vec::new();
+    let mut git_roots = Vec::new();
+    if let Some(bundle) = &opts.ca_bundle {
+        let pem = fs::read(bundle)
+            .with_context(|| format!("could not read CA bundle {}", bundle.display()))?;
+        certs = reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("{} is not a PEM certificate bundle", bundle.display()))?;
+        git_roots = X509::stack_from_pem(&pem)
+            .with_context(|| format!("{} is not a PEM certificate bundle", bundle.display()))?;
+    }
+    TLS.get_or_init(|| Tls {
+        opts,
+        certs,
+        git_roots,
+    });
+    Ok(())
+}
+
+fn get() -> Option<&'static Tls> {
+    TLS.get()
+}
+
+/// A client builder with our user agent that trusts the CA bundle, or everything under
+/// `--insecure-tls`.
+pub fn http_client() -> reqwest::blocking::ClientBuilder {
+    let mut builder = reqwest::blocking::Client::builder().user_agent(crate::USER_AGENT);
+    if let Some(tls) = get() {
+        for cert in &tls.certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        builder = builder.danger_accept_invalid_certs(tls.opts.insecure);
+    }
+    builder
+}
+
+/// libgit2's `certificate_check` callback. Everything is accepted under
+/// `--insecure-tls`; otherwise a server certificate issued for `host` by a CA in the
+/// bundle is accepted, and anything else is left to libgit2's own check against the
+/// system store.
+///
+/// libgit2 only hands over the server's own certificate, not the chain it sent, so
+/// the bundle has to hold any intermediate certificates as well as the root.
+pub fn check_git_certificate(
+    cert: &Cert<'_>,
+    host: &str,
+) -> Result<CertificateCheckStatus, git2::Error> {
+    let Some(tls) = get() else {
+        return Ok(CertificateCheckStatus::CertificatePassthrough);
+    };
+    if tls.opts.insecure {
+        return Ok(CertificateCheckStatus::CertificateOk);
+    }
+    let trusted = cert
+        .as_x509()
+        .is_some_and(|x509| signed_by_bundle(&tls.git_roots, x509.data(), host));
+    Ok(if trusted {
+        CertificateCheckStatus::CertificateOk
+    } else {
+        CertificateCheckStatus::CertificatePassthrough
+    })
+}
+
+/// Whether the DER certificate `der` is valid for `host` and chains up to `roots`.
+fn signed_by_bundle(roots: &[X509], der: &[u8], host: &str) -> bool {
+    if roots.is_empty() {
+        return false;
+    }
+    let verify = || -> Result<bool, openssl::error::ErrorStack> {
+        let leaf = X509::from_der(der)?;
+        let mut store = X509StoreBuilder::new()?;
+        for root in roots {
+            store.add_cert(root.clone())?;
+        }
+        let mut param = X509VerifyParam::new()?;
+        param.set_host(host)?;
+        store.set_param(&param)?;
+        let store = store.build();
+        let chain = Stack::new()?;
+        let mut context = X509StoreContext::new()?;
+        context.init(&store, &leaf, &chain, |context| context.verify_cert())
+    };
+    verify().unwrap_or(false)
+}
+
+/// Pass the same settings on to a `git` process.
+pub fn configure_git(cmd: &mut Command) {
+    let Some(tls) = get() else {
+        return;
+    };
+    if let Some(bundle) = &tls.opts.ca_bundle {
+        cmd.env("GIT_SSL_CAINFO", bundle);
+    }
+    if tls.opts.insecure {
+        cmd.env("GIT_SSL_NO_VERIFY", "true");
+    }
+}
@@ -0,0 +1,81 @@
+//! Leaving vendored C and C++ sources out of a checkout, for `--skip-vendored`.
+//!
+//! `-sys` crates often carry a whole C library next to their build script. Those
+//! directories are excluded with git's own sparse checkout, so the objects stay in the
+//! repository and `git sparse-checkout disable` brings the files back.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::clone::{self, CloneError};
+
+/// Extensions that mark a directory as holding C or C++ sources.
+const C_EXTENSIONS: [&str; 7] = ["c", "cc", "cpp", "cxx", "h", "hh", "hpp"];
+
+/// Package subdirectories that are Rust's own, whatever else they contain.
+const RUST_DIRS: [&str; 4] = ["src", "tests", "examples", "benches"];
+
+/// Directories directly inside `package_dir` that look like vendored C: C or C++ files
+/// somewhere below them and no Rust. Only packages with a `build.rs` have any.
+pub fn find(package_dir: &Path) -> Vec<PathBuf> {
+    if !package_dir.join("build.rs").is_file() {
+        return Vec::new();
+    }
+    let Ok(entries) = fs::read_dir(package_dir) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && name != "target" && !RUST_DIRS.contains(&name.as_ref())
+        })
+        .map(|entry| entry.path())
+        .filter(|dir| matches!(languages(dir), (true, false)))
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Whether the tree under `dir` has C/C++ files, and whether it has Rust files.
+fn languages(dir: &Path) -> (bool, bool) {
+    let mut c = false;
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("rs") => return (c, true),
+                Some(ext) if C_EXTENSIONS.contains(&ext) => c = true,
+                _ => {}
+            }
+        }
+    }
+    (c, false)
+}
+
+/// Turn on a sparse checkout of `repo_dir` that leaves out `dirs`, which must be inside
+/// it. Needs the `git` CLI.
+pub fn exclude(repo_dir: &Path, dirs: &[PathBuf]) -> Result<(), CloneError> {
+    let mut cmd = clone::git_command();
+    cmd.arg("-C")
+        .arg(repo_dir)
+        .args(["sparse-checkout", "set", "--no-cone", "/*"]);
+    for dir in dirs {
+        let relative = dir.strip_prefix(repo_dir).unwrap_or(dir);
+        cmd.arg(format!("!/{}/", relative.to_string_lossy()));
+    }
+    clone::run_git(cmd)
+}